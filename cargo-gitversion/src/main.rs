@@ -0,0 +1,317 @@
+//! `cargo gitversion` prints the version `dotnet-gitversion-build` calculates for a crate.
+
+use dotnet_gitversion_build::emit::{Directive, Sink};
+use dotnet_gitversion_build::manifest::{self, SyncMode};
+use dotnet_gitversion_build::{Cache, GitVersion, GitVersionBuild, DEFAULT_ENV_PREFIX};
+use serde_json::{Map, Value};
+use std::env;
+use std::error::Error;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::process;
+
+const USAGE: &str = "\
+Prints the version dotnet-gitversion-build calculates for the crate in the current directory.
+
+USAGE:
+    cargo gitversion [OPTIONS]
+    cargo gitversion sync [--check] [--manifest-path <PATH>] [OPTIONS]
+
+SUBCOMMANDS:
+    sync                       Sets the versions in Cargo.toml and Cargo.lock to the SemVer
+
+OPTIONS:
+    -f, --format <FORMAT>      Output format: table, json or env [default: table]
+        --target-path <DIR>    The directory inside the repository (/targetpath)
+    -b, --branch <BRANCH>      The branch to calculate the version for (/b)
+    -c, --commit <COMMIT>      The commit to calculate the version for (/c)
+        --config <FILE>        The GitVersion configuration file (/config)
+        --executable <PATH>    The GitVersion executable to call
+        --env-prefix <PREFIX>  The prefix of the variables printed by --format env
+                               [default: GITVERSION_]
+        --cache-dir <DIR>      Caches the output of GitVersion in the given directory
+        --check                Fails instead of syncing if a version differs (sync only)
+        --manifest-path <PATH> The root Cargo.toml to sync [default: Cargo.toml]
+    -h, --help                 Prints this help
+    -V, --version              Prints the version of cargo-gitversion";
+
+/// How the variables are printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    /// Aligned `Name  Value` lines, using GitVersion's field names.
+    Table,
+    /// GitVersion's JSON output.
+    Json,
+    /// `NAME=value` lines as passed to `cargo:rustc-env`.
+    Env,
+}
+
+/// What the command line asks for.
+#[derive(Debug)]
+enum Command {
+    Help,
+    Version,
+    Print {
+        format: Format,
+        env_prefix: String,
+        config: Box<GitVersionBuild>,
+    },
+    Sync {
+        mode: SyncMode,
+        manifest_path: PathBuf,
+        config: Box<GitVersionBuild>,
+    },
+}
+
+/// Parses the arguments following the program name, and `gitversion` when run via `cargo`.
+fn parse_args<I>(args: I) -> Result<Command, String>
+where
+    I: IntoIterator<Item = OsString>,
+{
+    let mut args = args.into_iter().peekable();
+    if matches!(args.peek(), Some(arg) if arg == "gitversion") {
+        args.next();
+    }
+    let sync = matches!(args.peek(), Some(arg) if arg == "sync");
+    if sync {
+        args.next();
+    }
+
+    let mut format = Format::Table;
+    let mut mode = SyncMode::Write;
+    let mut manifest_path = PathBuf::from("Cargo.toml");
+    let mut env_prefix = DEFAULT_ENV_PREFIX.to_owned();
+    let mut config = GitVersionBuild::new();
+    while let Some(arg) = args.next() {
+        let arg = arg.to_string_lossy().into_owned();
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) if name.starts_with("--") => (name.to_owned(), Some(value.into())),
+            _ => (arg.clone(), None),
+        };
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("{} requires a value", name))
+        };
+        match name.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            "-f" | "--format" => {
+                format = match value()?.to_string_lossy().as_ref() {
+                    "table" => Format::Table,
+                    "json" => Format::Json,
+                    "env" => Format::Env,
+                    other => return Err(format!("unknown format {:?}", other)),
+                }
+            }
+            "--target-path" => config = config.target_path(value()?),
+            "-b" | "--branch" => config = config.branch(value()?.to_string_lossy()),
+            "-c" | "--commit" => config = config.commit(value()?.to_string_lossy()),
+            "--config" => config = config.config_file(value()?),
+            "--executable" => config = config.executable(value()?),
+            "--env-prefix" => env_prefix = value()?.to_string_lossy().into_owned(),
+            "--cache-dir" => config = config.cache(Cache::Dir(value()?.into())),
+            "--check" if sync => mode = SyncMode::Check,
+            "--manifest-path" if sync => manifest_path = value()?.into(),
+            _ => return Err(format!("unexpected argument {:?}", arg)),
+        }
+    }
+    if sync {
+        return Ok(Command::Sync {
+            mode,
+            manifest_path,
+            config: Box::new(config.rerun_directives(false)),
+        });
+    }
+    Ok(Command::Print {
+        format,
+        config: Box::new(config.env_prefix(&env_prefix).rerun_directives(false)),
+        env_prefix,
+    })
+}
+
+/// Prints warnings to stderr, dropping the directives only meaningful to Cargo.
+struct Stderr;
+
+impl Sink for Stderr {
+    fn emit(&mut self, directive: Directive<'_>) {
+        if let Directive::Warning(message) = directive {
+            eprintln!("warning: {}", message);
+        }
+    }
+}
+
+/// Collects the `cargo:rustc-env` variables as `NAME=value` lines.
+struct EnvLines(Vec<String>);
+
+impl Sink for EnvLines {
+    fn emit(&mut self, directive: Directive<'_>) {
+        if let Directive::RustcEnv { name, value } = directive {
+            self.0.push(format!("{}={}", name, value));
+        }
+    }
+}
+
+/// The fields of `gv` by their GitVersion name, including the `Source`.
+fn fields(gv: &GitVersion) -> Map<String, Value> {
+    let mut fields = match serde_json::to_value(gv) {
+        Ok(Value::Object(fields)) => fields,
+        _ => Map::new(),
+    };
+    fields.insert("Source".into(), gv.source.to_string().into());
+    fields
+}
+
+fn render(gv: &GitVersion, format: Format, config: &GitVersionBuild, prefix: &str) -> String {
+    match format {
+        Format::Table => {
+            let fields = fields(gv);
+            let width = fields.keys().map(String::len).max().unwrap_or_default();
+            let lines: Vec<String> = fields
+                .iter()
+                .map(|(name, value)| {
+                    let value = match value {
+                        Value::String(value) => value.clone(),
+                        Value::Null => String::new(),
+                        other => other.to_string(),
+                    };
+                    format!("{:width$}  {}", name, value, width = width)
+                        .trim_end()
+                        .to_owned()
+                })
+                .collect();
+            lines.join("\n")
+        }
+        Format::Json => serde_json::to_string_pretty(&fields(gv)).unwrap_or_default(),
+        Format::Env => {
+            let mut lines = EnvLines(Vec::new());
+            config.emit(gv, &mut lines);
+            lines.0.push(format!("{}SOURCE={}", prefix, gv.source));
+            lines.0.join("\n")
+        }
+    }
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let (format, env_prefix, config) = match parse_args(env::args_os().skip(1))? {
+        Command::Help => {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+        Command::Version => {
+            println!("cargo-gitversion {}", env!("CARGO_PKG_VERSION"));
+            return Ok(());
+        }
+        Command::Print {
+            format,
+            env_prefix,
+            config,
+        } => (format, env_prefix, config),
+        Command::Sync {
+            mode,
+            manifest_path,
+            config,
+        } => {
+            let gv = config.calculate(&mut Stderr)?;
+            let changed = manifest::sync_versions(&manifest_path, &gv.semver, mode)?;
+            for path in changed {
+                println!("Updated {} to {}", path.display(), gv.semver);
+            }
+            return Ok(());
+        }
+    };
+
+    let gv = config.calculate(&mut Stderr)?;
+    println!("{}", render(&gv, format, &config, &env_prefix));
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("error: {}", e);
+        let mut source = e.source();
+        while let Some(cause) = source {
+            eprintln!("  caused by: {}", cause);
+            source = cause.source();
+        }
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    pub fn parses_arguments() {
+        match parse_args(args(&["gitversion", "--format=json", "-b", "main"])) {
+            Ok(Command::Print {
+                format, env_prefix, ..
+            }) => {
+                assert_eq!(format, Format::Json);
+                assert_eq!(env_prefix, DEFAULT_ENV_PREFIX);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(matches!(parse_args(args(&["-h"])), Ok(Command::Help)));
+        assert!(matches!(
+            parse_args(args(&["gitversion", "sync", "--check"])),
+            Ok(Command::Sync {
+                mode: SyncMode::Check,
+                ..
+            })
+        ));
+        assert_eq!(
+            parse_args(args(&["--check"])).unwrap_err(),
+            "unexpected argument \"--check\""
+        );
+        assert_eq!(
+            parse_args(args(&["--format", "yaml"])).unwrap_err(),
+            "unknown format \"yaml\""
+        );
+        assert_eq!(
+            parse_args(args(&["--branch"])).unwrap_err(),
+            "--branch requires a value"
+        );
+    }
+
+    #[test]
+    pub fn renders_formats() -> Result<(), Box<dyn Error>> {
+        let gv = GitVersion::from_json(
+            r#"{"Major": 1, "Minor": 2, "Patch": 3, "PreReleaseTag": "", "PreReleaseTagWithDash": "",
+                "PreReleaseLabel": "", "PreReleaseLabelWithDash": "", "PreReleaseNumber": null,
+                "WeightedPreReleaseNumber": 60000, "BuildMetaData": null, "FullBuildMetaData": "Sha.0123456",
+                "MajorMinorPatch": "1.2.3", "SemVer": "1.2.3", "AssemblySemVer": "1.2.3.0",
+                "AssemblySemFileVer": "1.2.3.0", "InformationalVersion": "1.2.3+Sha.0123456",
+                "FullSemVer": "1.2.3", "BranchName": "main", "EscapedBranchName": "main",
+                "Sha": "0123456", "ShortSha": "0123456", "VersionSourceSha": "0123456",
+                "CommitsSinceVersionSource": 0, "UncommittedChanges": 0, "CommitDate": "2024-03-01"}"#,
+        )?;
+        let config = GitVersionBuild::new().env_prefix("MY_");
+
+        let table = render(&gv, Format::Table, &config, "MY_");
+        let row = |name: &str| {
+            table
+                .lines()
+                .find(|line| line.split_whitespace().next() == Some(name))
+                .map(|line| line[name.len()..].trim().to_owned())
+        };
+        assert_eq!(row("FullSemVer").as_deref(), Some("1.2.3"));
+        assert_eq!(row("PreReleaseTag").as_deref(), Some(""));
+        assert_eq!(row("Source").as_deref(), Some("GitVersion"));
+
+        let json: Value = serde_json::from_str(&render(&gv, Format::Json, &config, "MY_"))?;
+        assert_eq!(json["SemVer"], "1.2.3");
+        assert_eq!(json["Source"], "GitVersion");
+
+        let env = render(&gv, Format::Env, &config, "MY_");
+        assert!(env.starts_with("MY_MAJOR=1\n"));
+        assert!(env.ends_with("\nMY_SOURCE=GitVersion"));
+        Ok(())
+    }
+}
@@ -0,0 +1,40 @@
+use serde_json::Value;
+use std::error::Error;
+use std::fs;
+use std::process::Command;
+use tempfile::TempDir;
+
+#[test]
+pub fn packaged_crate_uses_manifest_version() -> Result<(), Box<dyn Error>> {
+    let dir = TempDir::new()?;
+    fs::write(
+        dir.path().join("Cargo.toml"),
+        "[package]\nedition = \"2018\"\nname = \"vendored\"\nversion = \"1.4.2\"\n",
+    )?;
+    fs::write(
+        dir.path().join(".cargo_vcs_info.json"),
+        r#"{"git": {"sha1": "645a21e7b6358e9b72978a1b46cbd6c55a85a9af"}, "path_in_vcs": ""}"#,
+    )?;
+
+    let mut command = Command::new(env!("CARGO_BIN_EXE_cargo-gitversion"));
+    command
+        .args(["gitversion", "--format", "json"])
+        .current_dir(dir.path());
+    for name in &[
+        "CARGO_MANIFEST_DIR",
+        "CARGO_PKG_VERSION_MAJOR",
+        "CARGO_PKG_VERSION_MINOR",
+        "CARGO_PKG_VERSION_PATCH",
+        "CARGO_PKG_VERSION_PRE",
+    ] {
+        command.env_remove(name);
+    }
+    let out = command.output()?;
+    assert!(out.status.success(), "{:?}", out);
+
+    let fields: Value = serde_json::from_slice(&out.stdout)?;
+    assert_eq!(fields["FullSemVer"], "1.4.2");
+    assert_eq!(fields["ShortSha"], "645a21e");
+    assert_eq!(fields["Source"], ".cargo_vcs_info.json");
+    Ok(())
+}
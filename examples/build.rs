@@ -1,7 +1,12 @@
+use dotnet_gitversion_build::{FallbackPolicy, GitVersionBuild};
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Build the `gitversion.rs` file.
     // The intermediate representation is returned for use by subsequent build steps.
-    let _version = dotnet_gitversion_build::build()?;
+    // Without GitVersion installed, the version is taken from Cargo.toml instead.
+    let _version = GitVersionBuild::new()
+        .fallback(FallbackPolicy::UseCargoPkgVersion)
+        .run()?;
 
     Ok(())
 }
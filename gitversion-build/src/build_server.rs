@@ -0,0 +1,236 @@
+use crate::emit::{Directive, Sink};
+use crate::gitversion::{GitVersion, VersionSource};
+use crate::{Error, Result};
+use serde_json::{Map, Value};
+use std::env;
+use std::ffi::OsString;
+
+/// Determines whether the `GitVersion_*` variables exported by build servers are used,
+/// e.g. by the GitTools actions or `dotnet-gitversion /output buildserver`.
+///
+/// Variable names are matched case-insensitively, so both `GitVersion_FullSemVer`
+/// and `GITVERSION_FULLSEMVER` are recognized.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BuildServerEnv {
+    /// Ignores the variables. This is the default.
+    #[default]
+    Ignore,
+    /// Uses the variables instead of calling the executable. Precomputed JSON provided
+    /// through `DOTNET_GITVERSION_JSON` or `DOTNET_GITVERSION_JSON_FILE` still takes
+    /// precedence, but must agree with the variables.
+    Use,
+    /// Calculates the version as usual, but fails the build if it disagrees with the variables.
+    Verify,
+}
+
+/// The prefix of the variables exported by build servers.
+const PREFIX: &str = "GitVersion_";
+
+/// The fields GitVersion reports as numbers; all others are strings.
+const NUMBER_FIELDS: &[&str] = &[
+    "Major",
+    "Minor",
+    "Patch",
+    "PreReleaseNumber",
+    "WeightedPreReleaseNumber",
+    "BuildMetaData",
+    "CommitsSinceVersionSource",
+    "UncommittedChanges",
+];
+
+const STRING_FIELDS: &[&str] = &[
+    "PreReleaseTag",
+    "PreReleaseTagWithDash",
+    "PreReleaseLabel",
+    "PreReleaseLabelWithDash",
+    "BuildMetaDataPadded",
+    "FullBuildMetaData",
+    "MajorMinorPatch",
+    "SemVer",
+    "LegacySemVer",
+    "LegacySemVerPadded",
+    "AssemblySemVer",
+    "AssemblySemFileVer",
+    "InformationalVersion",
+    "FullSemVer",
+    "BranchName",
+    "EscapedBranchName",
+    "Sha",
+    "ShortSha",
+    "NuGetVersionV2",
+    "NuGetVersion",
+    "NuGetPreReleaseTagV2",
+    "NuGetPreReleaseTag",
+    "VersionSourceSha",
+    "CommitsSinceVersionSourcePadded",
+    "CommitDate",
+];
+
+/// Prints `cargo:rerun-if-env-changed` directives for all build server variables,
+/// both as `GitVersion_FullSemVer` and as the `GITVERSION_FULLSEMVER` used on Azure Pipelines.
+/// Cargo compares variable names case-sensitively.
+pub(crate) fn emit_rerun_directives(sink: &mut dyn Sink) {
+    for field in NUMBER_FIELDS.iter().chain(STRING_FIELDS) {
+        let name = format!("{}{}", PREFIX, field);
+        sink.emit(Directive::RerunIfEnvChanged(&name));
+        sink.emit(Directive::RerunIfEnvChanged(&name.to_uppercase()));
+    }
+}
+
+/// Reconstructs the version from the build server variables of this process.
+pub(crate) fn from_env() -> Result<Option<GitVersion>> {
+    from_vars(env::vars_os())
+}
+
+/// Reconstructs the version from `vars`, or returns `None` if `GitVersion_Major` is missing.
+fn from_vars<I>(vars: I) -> Result<Option<GitVersion>>
+where
+    I: IntoIterator<Item = (OsString, OsString)>,
+{
+    let mut map = Map::new();
+    for (name, value) in vars {
+        let name = name.to_string_lossy();
+        let field = match strip_prefix_ignore_case(&name, PREFIX) {
+            Some(field) => field,
+            None => continue,
+        };
+        let value = value.to_string_lossy().into_owned();
+        if let Some(field) = find_ignore_case(NUMBER_FIELDS, field) {
+            // Number fields such as `BuildMetaData` are exported as empty strings when unset.
+            let number = match value.parse::<u64>() {
+                Ok(number) => Value::from(number),
+                Err(_) if value.is_empty() => Value::Null,
+                Err(_) => Value::String(value),
+            };
+            map.insert(field.into(), number);
+        } else if let Some(field) = find_ignore_case(STRING_FIELDS, field) {
+            map.insert(field.into(), Value::String(value));
+        }
+    }
+
+    if !map.contains_key("Major") {
+        return Ok(None);
+    }
+    let mut gv = GitVersion::from_json(&Value::Object(map).to_string())?;
+    gv.source = VersionSource::BuildServer;
+    Ok(Some(gv))
+}
+
+/// Fails if the version reconstructed from the variables disagrees with `calculated`
+/// in either `FullSemVer` or `Sha`.
+pub(crate) fn verify(from_env: &GitVersion, calculated: &GitVersion) -> Result<()> {
+    let compared = [
+        ("FullSemVer", &from_env.full_semver, &calculated.full_semver),
+        ("Sha", &from_env.sha, &calculated.sha),
+    ];
+    for (field, expected, actual) in compared.iter() {
+        if expected != actual {
+            return Err(Error::BuildServerMismatch {
+                variable: format!("{}{}", PREFIX, field),
+                expected: expected.to_string(),
+                actual: actual.to_string(),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn strip_prefix_ignore_case<'a>(name: &'a str, prefix: &str) -> Option<&'a str> {
+    match name.get(..prefix.len()) {
+        Some(start) if start.eq_ignore_ascii_case(prefix) => Some(&name[prefix.len()..]),
+        _ => None,
+    }
+}
+
+fn find_ignore_case(fields: &[&'static str], name: &str) -> Option<&'static str> {
+    fields
+        .iter()
+        .copied()
+        .find(|field| field.eq_ignore_ascii_case(name))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(OsString, OsString)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.into(), value.into()))
+            .collect()
+    }
+
+    fn build_server_vars(full_semver: &str) -> Vec<(OsString, OsString)> {
+        vars(&[
+            ("GITVERSION_MAJOR", "1"),
+            ("GitVersion_Minor", "2"),
+            ("GitVersion_Patch", "3"),
+            ("GitVersion_PreReleaseTag", ""),
+            ("GitVersion_PreReleaseTagWithDash", ""),
+            ("GitVersion_PreReleaseLabel", ""),
+            ("GitVersion_PreReleaseLabelWithDash", ""),
+            ("GitVersion_PreReleaseNumber", ""),
+            ("GitVersion_WeightedPreReleaseNumber", "60000"),
+            ("GitVersion_BuildMetaData", "5"),
+            ("GitVersion_FullBuildMetaData", "5.Branch.main.Sha.0123456"),
+            ("GitVersion_MajorMinorPatch", "1.2.3"),
+            ("GitVersion_SemVer", "1.2.3"),
+            ("GitVersion_AssemblySemVer", "1.2.3.0"),
+            ("GitVersion_AssemblySemFileVer", "1.2.3.0"),
+            (
+                "GitVersion_InformationalVersion",
+                "1.2.3+5.Branch.main.Sha.0123456",
+            ),
+            ("GitVersion_FullSemVer", full_semver),
+            ("GitVersion_BranchName", "main"),
+            ("GitVersion_EscapedBranchName", "main"),
+            ("GitVersion_Sha", "0123456"),
+            ("GitVersion_ShortSha", "0123456"),
+            ("GitVersion_VersionSourceSha", "7654321"),
+            ("GitVersion_CommitsSinceVersionSource", "5"),
+            ("GitVersion_UncommittedChanges", "0"),
+            ("GitVersion_CommitDate", "2021-07-17"),
+            ("PATH", "/usr/bin"),
+        ])
+    }
+
+    #[test]
+    pub fn reconstructs_version_from_variables() -> Result<()> {
+        let gv = from_vars(build_server_vars("1.2.3+5"))?.expect("version");
+        assert_eq!(gv.major, 1);
+        assert_eq!(gv.pre_release_number, None);
+        assert_eq!(gv.build_meta_data, Some(5));
+        assert_eq!(gv.sha, "0123456");
+
+        assert!(from_vars(vars(&[("PATH", "/usr/bin")]))?.is_none());
+        match from_vars(vars(&[("GitVersion_Major", "1")])) {
+            Err(Error::InvalidJson { field, .. }) => assert_eq!(field.as_deref(), Some("Minor")),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        Ok(())
+    }
+
+    #[test]
+    pub fn rerun_directives_cover_upper_case_names() {
+        let mut lines = Vec::new();
+        emit_rerun_directives(&mut lines);
+        assert!(lines.contains(&"cargo:rerun-if-env-changed=GitVersion_FullSemVer".to_owned()));
+        assert!(lines.contains(&"cargo:rerun-if-env-changed=GITVERSION_FULLSEMVER".to_owned()));
+        assert_eq!(lines.len(), 2 * (NUMBER_FIELDS.len() + STRING_FIELDS.len()));
+    }
+
+    #[test]
+    pub fn detects_mismatch() -> Result<()> {
+        let from_env = from_vars(build_server_vars("1.2.3+5"))?.expect("version");
+        let calculated = from_vars(build_server_vars("1.2.4+1"))?.expect("version");
+
+        verify(&from_env, &from_env)?;
+        match verify(&from_env, &calculated) {
+            Err(Error::BuildServerMismatch { variable, .. }) => {
+                assert_eq!(variable, "GitVersion_FullSemVer")
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        Ok(())
+    }
+}
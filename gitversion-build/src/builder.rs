@@ -0,0 +1,581 @@
+use crate::build_server::{self, BuildServerEnv};
+use crate::cache::Cache;
+use crate::ci;
+use crate::config::{ConfigOverride, ConfigValidation};
+use crate::diagnostics::Verbosity;
+use crate::emit::{Cargo, Directive, Discard, Sink};
+use crate::fallback::FallbackPolicy;
+use crate::gitversion::GitVersion;
+use crate::package_version::PackageVersionCheck;
+use crate::release::ReleasePolicy;
+use crate::repository::{emit_rerun_directives, Repository, CONFIG_FILE};
+use crate::tool::{self, Tool};
+use crate::{calculate_version, emit_cfgs, emit_version, write_version_file, Error, Result};
+use semver::VersionReq;
+use std::env;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
+
+/// The executable tried first when no executable is configured.
+pub const DEFAULT_EXECUTABLE: &str = "dotnet-gitversion";
+
+/// The name of the generated file in the `OUT_DIR` directory.
+pub const DEFAULT_OUTPUT_FILE: &str = "gitversion.rs";
+
+/// The name of the configuration file merged with the branch overrides in `OUT_DIR`.
+const MERGED_CONFIG_FILE: &str = "GitVersion.merged.yml";
+
+/// The prefix of the generated environment variables.
+pub const DEFAULT_ENV_PREFIX: &str = "GITVERSION_";
+
+/// The environment variable providing precomputed GitVersion JSON,
+/// used instead of calling the executable.
+pub const JSON_ENV_VAR: &str = "DOTNET_GITVERSION_JSON";
+
+/// The environment variable providing the path of a precomputed GitVersion JSON file,
+/// used instead of calling the executable.
+pub const JSON_FILE_ENV_VAR: &str = "DOTNET_GITVERSION_JSON_FILE";
+
+/// Selects how the version is calculated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// Calls the GitVersion executable. With the `native` feature enabled, the version
+    /// is calculated natively if the executable is not installed. This is the default.
+    #[default]
+    Auto,
+    /// Always calls the GitVersion executable.
+    Tool,
+    /// Calculates the version in Rust using the `git` command line; no .NET required.
+    #[cfg(feature = "native")]
+    Native,
+}
+
+/// Determines how a GitVersion.Tool not satisfying the required version is reported.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ToolVersionCheck {
+    /// Reports a `cargo:warning`.
+    Warn,
+    /// Fails the build. This is the default.
+    #[default]
+    Deny,
+}
+
+/// The repository, branch, commit and configuration file the version is calculated for.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Target {
+    /// The directory inside the repository, passed as `/targetpath`.
+    pub dir: PathBuf,
+    /// The branch passed as `/b`.
+    pub branch: Option<String>,
+    /// The commit passed as `/c`.
+    pub commit: Option<String>,
+    /// The configuration file passed as `/config`.
+    pub config_file: Option<PathBuf>,
+    /// The settings passed as `/overrideconfig`, or merged into the configuration file.
+    pub overrides: ConfigOverride,
+}
+
+impl Target {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            ..Self::default()
+        }
+    }
+
+    /// The arguments selecting this target on the command line of the executable.
+    pub fn args(&self) -> Vec<OsString> {
+        let mut args: Vec<OsString> = vec!["/targetpath".into(), self.dir.clone().into()];
+        if let Some(config_file) = &self.config_file {
+            args.extend(vec!["/config".into(), config_file.clone().into()]);
+        }
+        args.extend(self.selection_args());
+        args
+    }
+
+    /// The [`args`](Self::args) the output depends on besides the repository and the
+    /// content of the configuration file, which the cache key covers separately,
+    /// plus the branch overrides merged into that file.
+    pub fn cache_args(&self) -> Vec<OsString> {
+        let mut args = self.selection_args();
+        args.extend(self.overrides.branch_entries());
+        args
+    }
+
+    fn selection_args(&self) -> Vec<OsString> {
+        let mut args = Vec::new();
+        if let Some(branch) = &self.branch {
+            args.extend(vec!["/b".into(), branch.into()]);
+        }
+        if let Some(commit) = &self.commit {
+            args.extend(vec!["/c".into(), commit.into()]);
+        }
+        args.extend(self.overrides.args());
+        args
+    }
+
+    /// The [`args`](Self::args) for running the executable: the branch overrides, which
+    /// `/overrideconfig` does not support, are merged into a copy of the configuration
+    /// file passed as `/config` instead.
+    pub fn tool_args(&self) -> Result<Vec<OsString>> {
+        let merged = match env::var_os("OUT_DIR") {
+            Some(dir) => PathBuf::from(dir).join(MERGED_CONFIG_FILE),
+            None => env::temp_dir().join(format!("GitVersion-{}.yml", process::id())),
+        };
+        if !self
+            .overrides
+            .write_merged_config(&self.config_path(), &merged)?
+        {
+            return Ok(self.args());
+        }
+        let target = Self {
+            config_file: Some(merged),
+            ..self.clone()
+        };
+        Ok(target.args())
+    }
+
+    /// The configuration file in effect: the one given, or `GitVersion.yml`
+    /// in the root of the repository.
+    pub fn config_path(&self) -> PathBuf {
+        match &self.config_file {
+            Some(config_file) => config_file.clone(),
+            None => Repository::discover(&self.dir)
+                .map_or_else(|| self.dir.clone(), |r| r.work_dir)
+                .join(CONFIG_FILE),
+        }
+    }
+}
+
+/// Configures the `dotnet-gitversion` invocation and the generated output.
+///
+/// ```no_run
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use dotnet_gitversion_build::GitVersionBuild;
+///
+/// let _gv = GitVersionBuild::new()
+///     .executable("dotnet-gitversion")
+///     .output_file("version.rs")
+///     .env_prefix("MY_CRATE_")
+///     .run()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct GitVersionBuild {
+    executable: Option<OsString>,
+    tool_restore: bool,
+    tool_version: Option<String>,
+    tool_version_check: ToolVersionCheck,
+    timeout: Option<Duration>,
+    verbosity: Option<Verbosity>,
+    args: Vec<OsString>,
+    output_file: PathBuf,
+    env_prefix: String,
+    fallback: FallbackPolicy,
+    rerun_directives: bool,
+    rustc_cfgs: bool,
+    backend: Backend,
+    config_validation: ConfigValidation,
+    cache: Cache,
+    build_server_env: BuildServerEnv,
+    package_version_check: PackageVersionCheck,
+    release_policy: Option<ReleasePolicy>,
+    target_path: Option<PathBuf>,
+    branch: Option<String>,
+    commit: Option<String>,
+    config_file: Option<PathBuf>,
+    overrides: ConfigOverride,
+}
+
+impl GitVersionBuild {
+    /// Creates a configuration equivalent to calling [`build()`](crate::build).
+    pub fn new() -> Self {
+        Self {
+            executable: None,
+            tool_restore: false,
+            tool_version: None,
+            tool_version_check: ToolVersionCheck::default(),
+            timeout: None,
+            verbosity: None,
+            args: vec!["/nofetch".into()],
+            output_file: DEFAULT_OUTPUT_FILE.into(),
+            env_prefix: DEFAULT_ENV_PREFIX.into(),
+            fallback: FallbackPolicy::default(),
+            rerun_directives: true,
+            rustc_cfgs: true,
+            backend: Backend::default(),
+            config_validation: ConfigValidation::default(),
+            cache: Cache::default(),
+            build_server_env: BuildServerEnv::default(),
+            package_version_check: PackageVersionCheck::default(),
+            release_policy: None,
+            target_path: None,
+            branch: None,
+            commit: None,
+            config_file: None,
+            overrides: ConfigOverride::default(),
+        }
+    }
+
+    /// Sets the executable to call. By default, the first installed of `dotnet-gitversion`,
+    /// `dotnet gitversion` and `dotnet tool run dotnet-gitversion` is used; see [`Tool::candidates`].
+    pub fn executable<S: Into<OsString>>(mut self, executable: S) -> Self {
+        self.executable = Some(executable.into());
+        self
+    }
+
+    /// Runs `dotnet tool restore` before trying the `dotnet` based executables if the
+    /// crate is covered by a local tool manifest, e.g. `.config/dotnet-tools.json`.
+    /// Disabled by default.
+    pub fn tool_restore(mut self, enabled: bool) -> Self {
+        self.tool_restore = enabled;
+        self
+    }
+
+    /// Requires the version of GitVersion.Tool, as reported by `/version`, to satisfy
+    /// the semver `requirement`, e.g. `">=5.12, <6"`. The requirement is parsed by
+    /// [`run`](Self::run).
+    pub fn tool_version<S: Into<String>>(mut self, requirement: S) -> Self {
+        self.tool_version = Some(requirement.into());
+        self
+    }
+
+    /// Sets how a tool not satisfying the [`tool_version`](Self::tool_version)
+    /// requirement is reported. Defaults to [`ToolVersionCheck::Deny`].
+    pub fn tool_version_check(mut self, check: ToolVersionCheck) -> Self {
+        self.tool_version_check = check;
+        self
+    }
+
+    /// Kills the executable if it does not finish within `timeout`, failing with
+    /// [`Error::Timeout`](crate::Error::Timeout). The timeout applies to each `/version` query
+    /// and `dotnet tool restore` as well. By default, there is no timeout.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Has GitVersion write a log with the given verbosity, passed as `/verbosity` and `/l`.
+    /// Its warnings and errors are forwarded as `cargo:warning` lines, and the log is
+    /// included in the [`LOG_FILE`](crate::LOG_FILE) written to `OUT_DIR`.
+    pub fn verbosity(mut self, verbosity: Verbosity) -> Self {
+        self.verbosity = Some(verbosity);
+        self
+    }
+
+    /// Replaces the arguments passed to the executable. Defaults to `/nofetch`.
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<OsString>,
+    {
+        self.args = args.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Appends a single argument to the arguments passed to the executable.
+    pub fn arg<S: Into<OsString>>(mut self, arg: S) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Sets the directory GitVersion is run against, passed as `/targetpath`.
+    /// Relative paths are resolved against `CARGO_MANIFEST_DIR`, which is also the default.
+    pub fn target_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.target_path = Some(path.into());
+        self
+    }
+
+    /// Calculates the version for the given branch instead of the checked out one,
+    /// passed as `/b`.
+    pub fn branch<S: Into<String>>(mut self, branch: S) -> Self {
+        self.branch = Some(branch.into());
+        self
+    }
+
+    /// Calculates the version for the given commit instead of `HEAD`, passed as `/c`.
+    pub fn commit<S: Into<String>>(mut self, commit: S) -> Self {
+        self.commit = Some(commit.into());
+        self
+    }
+
+    /// Uses the given configuration file instead of `GitVersion.yml` in the root of the
+    /// repository, passed as `/config`. Relative paths are resolved against `CARGO_MANIFEST_DIR`.
+    pub fn config_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.config_file = Some(path.into());
+        self
+    }
+
+    /// Overrides settings of the configuration file, passed as `/overrideconfig`.
+    /// The overrides are validated by [`run`](Self::run).
+    pub fn override_config(mut self, overrides: ConfigOverride) -> Self {
+        self.overrides = overrides;
+        self
+    }
+
+    /// Sets the path of the generated file. Relative paths are resolved
+    /// against the `OUT_DIR` directory.
+    pub fn output_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.output_file = path.into();
+        self
+    }
+
+    /// Sets the prefix of the generated environment variables, e.g. `GITVERSION_`.
+    pub fn env_prefix<S: Into<String>>(mut self, prefix: S) -> Self {
+        self.env_prefix = prefix.into();
+        self
+    }
+
+    /// Sets what happens when the executable is missing or fails.
+    /// Defaults to [`FallbackPolicy::Error`].
+    pub fn fallback(mut self, policy: FallbackPolicy) -> Self {
+        self.fallback = policy;
+        self
+    }
+
+    /// Enables or disables the `cargo:rerun-if-changed` directives for the Git
+    /// state (`HEAD`, the current branch ref, `packed-refs`, `refs/tags`, the index)
+    /// and `GitVersion.yml`, and the `cargo:rerun-if-env-changed` directives for
+    /// [`JSON_ENV_VAR`] and [`JSON_FILE_ENV_VAR`]. Enabled by default.
+    ///
+    /// Note that emitting any such directive disables Cargo's default of rerunning
+    /// the build script whenever a file of the package changes.
+    pub fn rerun_directives(mut self, enabled: bool) -> Self {
+        self.rerun_directives = enabled;
+        self
+    }
+
+    /// Enables or disables the `cargo:rustc-cfg` flags `gitversion_prerelease`,
+    /// `gitversion_dirty`, `gitversion_branch="..."` and `gitversion_label="..."`,
+    /// declared via `cargo:rustc-check-cfg`. Enabled by default.
+    ///
+    /// This allows gating code on the version without runtime checks:
+    ///
+    /// ```
+    /// #[cfg(any(gitversion_prerelease, gitversion_dirty))]
+    /// fn debug_menu() {}
+    /// ```
+    pub fn rustc_cfgs(mut self, enabled: bool) -> Self {
+        self.rustc_cfgs = enabled;
+        self
+    }
+
+    /// Selects how the version is calculated. Defaults to [`Backend::Auto`].
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Sets how problems in `GitVersion.yml` are reported.
+    /// Defaults to [`ConfigValidation::Warn`].
+    pub fn config_validation(mut self, validation: ConfigValidation) -> Self {
+        self.config_validation = validation;
+        self
+    }
+
+    /// Sets where the output of the executable is cached between builds.
+    /// Defaults to [`Cache::Shared`].
+    pub fn cache(mut self, cache: Cache) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Sets whether the `GitVersion_*` variables exported by build servers are used.
+    /// Defaults to [`BuildServerEnv::Ignore`].
+    pub fn build_server_env(mut self, mode: BuildServerEnv) -> Self {
+        self.build_server_env = mode;
+        self
+    }
+
+    /// Sets whether `CARGO_PKG_VERSION` is compared against the calculated version and how
+    /// a difference is reported. Defaults to [`PackageVersionCheck::Ignore`].
+    pub fn package_version_check(mut self, check: PackageVersionCheck) -> Self {
+        self.package_version_check = check;
+        self
+    }
+
+    /// Sets the requirements the calculated version has to meet in release builds.
+    /// By default, there are none.
+    pub fn release_policy(mut self, policy: ReleasePolicy) -> Self {
+        self.release_policy = Some(policy);
+        self
+    }
+
+    /// Calls the executable, converts the JSON output and generates the output file.
+    ///
+    /// This prints the directives of [`calculate`](Self::calculate) and [`emit`](Self::emit)
+    /// for Cargo, then calls [`write_file`](Self::write_file).
+    pub fn run(&self) -> Result<GitVersion> {
+        let path = self.output_path()?;
+        let gv = self.calculate(&mut Cargo)?;
+        self.emit(&gv, &mut Cargo);
+        write_version_file(&path, &gv)?;
+        Ok(gv)
+    }
+
+    /// Calculates the version without generating any output, reporting rerun directives,
+    /// warnings and progress to `sink`. Unlike [`run`](Self::run), this does not require
+    /// `OUT_DIR` and can be used outside of build scripts, e.g. with [`Discard`](crate::emit::Discard).
+    ///
+    /// The [`fallback`](Self::fallback) policy is applied, after which the
+    /// [`package_version_check`](Self::package_version_check) and the
+    /// [`release_policy`](Self::release_policy) are checked.
+    pub fn calculate(&self, sink: &mut dyn Sink) -> Result<GitVersion> {
+        self.overrides.check()?;
+        self.get_tool_version()?;
+        if let Some(policy) = &self.release_policy {
+            policy.validate()?;
+        }
+        let target = self.target()?;
+        if self.rerun_directives {
+            emit_rerun_directives(&target.dir, sink);
+            if let Some(config_file) = &target.config_file {
+                sink.emit(Directive::RerunIfChanged(config_file));
+            }
+            sink.emit(Directive::RerunIfEnvChanged(JSON_ENV_VAR));
+            sink.emit(Directive::RerunIfEnvChanged(JSON_FILE_ENV_VAR));
+            ci::emit_rerun_directives(sink);
+            if self.build_server_env != BuildServerEnv::Ignore {
+                build_server::emit_rerun_directives(sink);
+            }
+            if let Some(policy) = &self.release_policy {
+                policy.emit_rerun_directives(sink);
+            }
+        }
+        self.config_validation.check(&target.config_path(), sink)?;
+        let gv = match calculate_version(self, sink) {
+            Ok(gv) => gv,
+            Err(e) => self.fallback.apply(e, sink)?,
+        };
+        let package_version = env::var("CARGO_PKG_VERSION").ok();
+        self.package_version_check
+            .check(package_version.as_deref(), &gv, sink)?;
+        if let Some(policy) = &self.release_policy {
+            policy.check(&gv)?;
+        }
+        Ok(gv)
+    }
+
+    /// Emits the [`env_prefix`](Self::env_prefix)ed `cargo:rustc-env` variables of `gv` to `sink`,
+    /// followed by the [`rustc_cfgs`](Self::rustc_cfgs).
+    pub fn emit(&self, gv: &GitVersion, sink: &mut dyn Sink) {
+        emit_version(gv, &self.env_prefix, sink);
+        if self.rustc_cfgs {
+            emit_cfgs(gv, sink);
+        }
+    }
+
+    /// Generates the [`output_file`](Self::output_file) defining `GIT_VERSION` for `gv`,
+    /// unless it is up to date, and returns its path.
+    pub fn write_file(&self, gv: &GitVersion) -> Result<PathBuf> {
+        let path = self.output_path()?;
+        write_version_file(&path, gv)?;
+        Ok(path)
+    }
+
+    /// The output file, resolved against `OUT_DIR` if relative.
+    fn output_path(&self) -> Result<PathBuf> {
+        if self.output_file.is_absolute() {
+            return Ok(self.output_file.clone());
+        }
+        let out_dir =
+            env::var_os("OUT_DIR").ok_or_else(|| Error::MissingEnvVar("OUT_DIR".into()))?;
+        Ok(PathBuf::from(out_dir).join(&self.output_file))
+    }
+
+    /// Finds the GitVersion invocation used by [`run`](Self::run): either the configured
+    /// [`executable`](Self::executable) or the first installed of the [`Tool::candidates`].
+    pub fn tool(&self) -> Result<Tool> {
+        self.find_tool(&mut Discard)
+    }
+
+    /// Like [`tool`](Self::tool), but reports failures of `dotnet tool restore` to `sink`.
+    pub(crate) fn find_tool(&self, sink: &mut dyn Sink) -> Result<Tool> {
+        match &self.executable {
+            Some(executable) => Ok(Tool::new(executable.clone())),
+            None => tool::discover(&self.target()?.dir, self.tool_restore, self.timeout, sink),
+        }
+    }
+
+    pub(crate) fn get_tool_version(&self) -> Result<Option<VersionReq>> {
+        match &self.tool_version {
+            Some(requirement) => Ok(Some(VersionReq::parse(requirement)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) fn get_tool_version_check(&self) -> ToolVersionCheck {
+        self.tool_version_check
+    }
+
+    pub(crate) fn get_timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    pub(crate) fn get_verbosity(&self) -> Option<Verbosity> {
+        self.verbosity
+    }
+
+    pub(crate) fn get_executable(&self) -> Option<&OsString> {
+        self.executable.as_ref()
+    }
+
+    pub(crate) fn get_args(&self) -> &[OsString] {
+        &self.args
+    }
+
+    pub(crate) fn get_cache(&self) -> &Cache {
+        &self.cache
+    }
+
+    pub(crate) fn get_build_server_env(&self) -> BuildServerEnv {
+        self.build_server_env
+    }
+
+    #[cfg(feature = "native")]
+    pub(crate) fn get_backend(&self) -> Backend {
+        self.backend
+    }
+
+    /// The target selected by [`target_path`](Self::target_path), [`branch`](Self::branch),
+    /// [`commit`](Self::commit) and [`config_file`](Self::config_file).
+    pub(crate) fn target(&self) -> Result<Target> {
+        let manifest_dir = self.manifest_dir()?;
+        let dir = match &self.target_path {
+            Some(path) => manifest_dir.join(path),
+            None => manifest_dir.clone(),
+        };
+        let branch = match &self.branch {
+            Some(branch) => Some(branch.clone()),
+            None => ci::detached_branch(&dir),
+        };
+        Ok(Target {
+            branch,
+            commit: self.commit.clone(),
+            overrides: self.overrides.clone(),
+            config_file: self
+                .config_file
+                .as_ref()
+                .map(|path| manifest_dir.join(path)),
+            ..Target::new(&dir)
+        })
+    }
+
+    /// The directory of the crate being built, or the current directory
+    /// outside of build scripts.
+    pub(crate) fn manifest_dir(&self) -> Result<PathBuf> {
+        match env::var_os("CARGO_MANIFEST_DIR") {
+            Some(dir) => Ok(PathBuf::from(dir)),
+            None => Ok(env::current_dir()?),
+        }
+    }
+}
+
+impl Default for GitVersionBuild {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,336 @@
+use crate::repository::{Repository, CONFIG_FILE};
+use crate::Result;
+use fs2::FileExt;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Where the output of the GitVersion executable is cached between builds.
+///
+/// Entries are keyed on the repository, the `HEAD` commit, the refs and tags, the index,
+/// the changes in the working tree, the content of `GitVersion.yml` and the command line,
+/// so the executable only runs when one of them changes. Crates in the same repository
+/// using the same configuration share an entry. Only the newest entry per command line is kept.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Cache {
+    /// Always calls the executable.
+    Disabled,
+    /// Caches in the `OUT_DIR` of the crate being built.
+    OutDir,
+    /// Caches in the profile directory below `target/`, e.g. `target/debug/gitversion-cache`,
+    /// shared by all crates of the workspace. This is the default.
+    #[default]
+    Shared,
+    /// Caches in the given directory.
+    Dir(PathBuf),
+}
+
+impl Cache {
+    fn dir(&self) -> Option<PathBuf> {
+        let out_dir = env::var_os("OUT_DIR").map(PathBuf::from);
+        match self {
+            Cache::Disabled => None,
+            Cache::OutDir => out_dir,
+            Cache::Shared => out_dir.map(|out_dir| shared_dir(&out_dir)),
+            Cache::Dir(dir) => Some(dir.clone()),
+        }
+    }
+
+    /// Returns the cached output for the state of the repository containing `start`
+    /// and the content of `files`, or calls `run` and caches its output, along with
+    /// whether the output was cached. Cached output rejected by `is_current` is replaced.
+    ///
+    /// Concurrent build scripts seeing the same state wait for a single invocation.
+    pub(crate) fn get_or_run<C, F>(
+        &self,
+        start: &Path,
+        inputs: &[&OsStr],
+        files: &[&Path],
+        is_current: C,
+        run: F,
+    ) -> Result<(String, bool)>
+    where
+        C: FnOnce(&str) -> bool,
+        F: FnOnce() -> Result<String>,
+    {
+        let (dir, (command, key)) = match (self.dir(), state_key(start, inputs, files)) {
+            (Some(dir), Some(key)) => (dir, key),
+            _ => return Ok((run()?, false)),
+        };
+
+        fs::create_dir_all(&dir)?;
+        // The lock is released when the file is closed.
+        let lock = File::create(dir.join(format!("{}.lock", key)))?;
+        lock.lock_exclusive()?;
+
+        let entry = dir.join(format!("{}.json", key));
+        match fs::read_to_string(&entry) {
+            Ok(json) if is_current(&json) => return Ok((json, true)),
+            _ => {}
+        }
+
+        let json = run()?;
+        let partial = dir.join(format!("{}.tmp", key));
+        fs::write(&partial, &json)?;
+        fs::rename(&partial, &entry)?;
+        prune(&dir, &command, &key);
+        Ok((json, false))
+    }
+}
+
+/// Removes the entries of the `command` key other than `key`, along with their lock files.
+/// Entries locked by other build scripts are left for later.
+fn prune(dir: &Path, command: &str, key: &str) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    let prefix = format!("{}-", command);
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        let stale = match path.file_name().and_then(OsStr::to_str) {
+            Some(name) => match name.strip_suffix(".json") {
+                Some(stem) => stem.starts_with(&prefix) && stem != key,
+                None => false,
+            },
+            None => false,
+        };
+        if !stale {
+            continue;
+        }
+        let lock_path = path.with_extension("lock");
+        let lock = match File::create(&lock_path) {
+            Ok(lock) => lock,
+            Err(_) => continue,
+        };
+        if lock.try_lock_exclusive().is_ok() {
+            let _ = fs::remove_file(&path);
+            let _ = fs::remove_file(&lock_path);
+        }
+    }
+}
+
+/// The profile directory containing the `build/` directory `OUT_DIR` is part of,
+/// or `OUT_DIR` itself if the layout is not recognized.
+fn shared_dir(out_dir: &Path) -> PathBuf {
+    out_dir
+        .ancestors()
+        .find(|dir| dir.file_name() == Some(OsStr::new("build")))
+        .and_then(Path::parent)
+        .unwrap_or(out_dir)
+        .join("gitversion-cache")
+}
+
+/// Hashes everything the output of GitVersion depends on, or returns `None` outside
+/// of a repository with commits. Returns the hash of the repository and command line,
+/// and the full key prefixed by it.
+///
+/// `start` only selects the repository and the applicable `GitVersion.yml`, which
+/// is hashed by content, so crates of one repository sharing a configuration share a key.
+fn state_key(start: &Path, inputs: &[&OsStr], files: &[&Path]) -> Option<(String, String)> {
+    let repo = Repository::discover(start)?;
+    let mut hasher = Sha256::new();
+
+    hash_bytes(&mut hasher, repo.work_dir.to_string_lossy().as_bytes());
+    for input in inputs {
+        hash_bytes(&mut hasher, input.to_string_lossy().as_bytes());
+    }
+    let command = format!("{:x}", hasher.clone().finalize());
+    let command = command[..16].to_owned();
+
+    hash_bytes(&mut hasher, repo.head_sha()?.as_bytes());
+    hash_status(&mut hasher, &repo.work_dir);
+    hash_file(&mut hasher, &repo.git_dir.join("index"));
+    hash_file(&mut hasher, &repo.common_dir.join("packed-refs"));
+    hash_refs(&mut hasher, &repo.common_dir.join("refs"));
+    // GitVersion prefers a `GitVersion.yml` in the target directory over the one in the root.
+    let config = [start.join(CONFIG_FILE), repo.work_dir.join(CONFIG_FILE)]
+        .iter()
+        .find(|path| path.is_file())
+        .cloned();
+    hash_content(&mut hasher, config.as_deref());
+    for file in files {
+        hash_content(&mut hasher, Some(file));
+    }
+
+    let key = format!("{}-{:x}", command, hasher.finalize());
+    Some((command, key))
+}
+
+fn hash_bytes(hasher: &mut Sha256, bytes: &[u8]) {
+    hasher.update((bytes.len() as u64).to_le_bytes());
+    hasher.update(bytes);
+}
+
+fn hash_file(hasher: &mut Sha256, path: &Path) {
+    hash_bytes(hasher, path.to_string_lossy().as_bytes());
+    match fs::read(path) {
+        Ok(content) => hash_bytes(hasher, &content),
+        Err(_) => hasher.update([0xff]),
+    }
+}
+
+/// Hashes the content of the file at `path`, or a marker if it is missing.
+fn hash_content(hasher: &mut Sha256, path: Option<&Path>) {
+    match path.and_then(|path| fs::read(path).ok()) {
+        Some(content) => hash_bytes(hasher, &content),
+        None => hasher.update([0xff]),
+    }
+}
+
+/// Hashes the uncommitted changes reported by `git status`, so that unstaged edits,
+/// which GitVersion counts in `UncommittedChanges`, invalidate the entry. Without Git,
+/// a marker is hashed instead and only staged changes are detected.
+fn hash_status(hasher: &mut Sha256, work_dir: &Path) {
+    // `--no-optional-locks` keeps `git status` from refreshing the hashed index.
+    let out = Command::new("git")
+        .args(["--no-optional-locks", "status", "--porcelain", "-z"])
+        .current_dir(work_dir)
+        .stderr(Stdio::null())
+        .output();
+    match out {
+        Ok(out) if out.status.success() => hash_bytes(hasher, &out.stdout),
+        _ => hasher.update([0xff]),
+    }
+}
+
+/// Hashes the names and targets of all loose refs below `dir`.
+fn hash_refs(hasher: &mut Sha256, dir: &Path) {
+    let mut entries: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|e| e.ok()).map(|e| e.path()).collect(),
+        Err(_) => return,
+    };
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            hash_refs(hasher, &path);
+        } else {
+            hash_file(hasher, &path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::Cell;
+    use tempfile::TempDir;
+
+    #[test]
+    pub fn reruns_only_when_state_changes() -> Result<()> {
+        let repo = TempDir::new()?;
+        let git_dir = repo.path().join(".git");
+        fs::create_dir_all(git_dir.join("refs").join("heads"))?;
+        fs::write(git_dir.join("HEAD"), "ref: refs/heads/main\n")?;
+        fs::write(git_dir.join("refs").join("heads").join("main"), "1111\n")?;
+        fs::write(git_dir.join("index"), "a")?;
+
+        let cache_dir = TempDir::new()?;
+        let cache = Cache::Dir(cache_dir.path().to_path_buf());
+        let runs = Cell::new(0);
+        let run = || {
+            runs.set(runs.get() + 1);
+            Ok(format!("{{\"Run\": {}}}", runs.get()))
+        };
+        let current = |_: &str| true;
+        let inputs = [OsStr::new("dotnet-gitversion")];
+
+        assert_eq!(
+            cache.get_or_run(repo.path(), &inputs, &[], current, run)?.0,
+            "{\"Run\": 1}"
+        );
+        assert_eq!(
+            cache.get_or_run(repo.path(), &inputs, &[], current, run)?.0,
+            "{\"Run\": 1}"
+        );
+
+        fs::write(git_dir.join("index"), "b")?;
+        assert_eq!(
+            cache.get_or_run(repo.path(), &inputs, &[], current, run)?.0,
+            "{\"Run\": 2}"
+        );
+        // The entry of the previous state is pruned.
+        assert_eq!(fs::read_dir(cache_dir.path())?.count(), 2);
+
+        fs::write(git_dir.join("refs").join("heads").join("main"), "2222\n")?;
+        assert_eq!(
+            cache.get_or_run(repo.path(), &inputs, &[], current, run)?.0,
+            "{\"Run\": 3}"
+        );
+
+        let stale = |json: &str| json != "{\"Run\": 3}";
+        assert_eq!(
+            cache.get_or_run(repo.path(), &inputs, &[], stale, run)?,
+            ("{\"Run\": 4}".to_owned(), false)
+        );
+        assert_eq!(
+            cache.get_or_run(repo.path(), &inputs, &[], stale, run)?,
+            ("{\"Run\": 4}".to_owned(), true)
+        );
+
+        assert_eq!(
+            Cache::Disabled
+                .get_or_run(repo.path(), &inputs, &[], current, run)?
+                .0,
+            "{\"Run\": 5}"
+        );
+        Ok(())
+    }
+
+    fn git(dir: &Path, args: &[&str]) {
+        let out = Command::new("git")
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .expect("git");
+        assert!(out.status.success(), "git {:?}: {:?}", args, out);
+    }
+
+    #[test]
+    pub fn crates_share_entry_until_worktree_changes() -> Result<()> {
+        let repo = TempDir::new()?;
+        fs::create_dir_all(repo.path().join("crates/a"))?;
+        fs::create_dir_all(repo.path().join("crates/b"))?;
+        fs::write(repo.path().join("crates/a/lib.rs"), "")?;
+        git(repo.path(), &["init", "-q"]);
+        git(repo.path(), &["add", "."]);
+        git(repo.path(), &["commit", "-q", "-m", "Initial commit"]);
+
+        let cache_dir = TempDir::new()?;
+        let cache = Cache::Dir(cache_dir.path().to_path_buf());
+        let runs = Cell::new(0);
+        let run = || {
+            runs.set(runs.get() + 1);
+            Ok(format!("{{\"Run\": {}}}", runs.get()))
+        };
+        let current = |_: &str| true;
+        let inputs = [OsStr::new("dotnet-gitversion")];
+
+        let a = repo.path().join("crates/a");
+        let b = repo.path().join("crates/b");
+        assert_eq!(
+            cache.get_or_run(&a, &inputs, &[], current, run)?,
+            ("{\"Run\": 1}".to_owned(), false)
+        );
+        assert_eq!(
+            cache.get_or_run(&b, &inputs, &[], current, run)?,
+            ("{\"Run\": 1}".to_owned(), true)
+        );
+
+        // An unstaged edit changes `UncommittedChanges`.
+        fs::write(a.join("lib.rs"), "fn f() {}")?;
+        assert_eq!(
+            cache.get_or_run(&b, &inputs, &[], current, run)?,
+            ("{\"Run\": 2}".to_owned(), false)
+        );
+        assert_eq!(
+            cache.get_or_run(&a, &inputs, &[], current, run)?,
+            ("{\"Run\": 2}".to_owned(), true)
+        );
+        Ok(())
+    }
+}
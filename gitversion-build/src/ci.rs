@@ -0,0 +1,166 @@
+use crate::builder::Target;
+use crate::emit::{self, Directive, Sink};
+use crate::repository::Repository;
+use crate::{Error, Result};
+use std::env;
+use std::ffi::OsString;
+use std::path::Path;
+
+/// The variables CI servers expose the branch being built in, in order of precedence:
+/// the source branch of GitHub pull requests, the ref of other GitHub workflows,
+/// the branch or tag name on GitLab, and the ref on Azure Pipelines.
+///
+/// `CI_COMMIT_REF_NAME` is skipped in GitLab tag pipelines, see [`GITLAB_TAG_VAR`].
+pub(crate) const BRANCH_VARS: &[&str] = &[
+    "GITHUB_HEAD_REF",
+    "GITHUB_REF",
+    "CI_COMMIT_REF_NAME",
+    "BUILD_SOURCEBRANCH",
+];
+
+/// The variable GitLab sets to the tag name in tag pipelines, in which
+/// `CI_COMMIT_REF_NAME` holds the tag rather than a branch.
+const GITLAB_TAG_VAR: &str = "CI_COMMIT_TAG";
+
+/// Prints `cargo:rerun-if-env-changed` directives for the [`BRANCH_VARS`]
+/// and [`GITLAB_TAG_VAR`].
+pub(crate) fn emit_rerun_directives(sink: &mut dyn Sink) {
+    for name in BRANCH_VARS.iter().chain(Some(&GITLAB_TAG_VAR)) {
+        sink.emit(Directive::RerunIfEnvChanged(name));
+    }
+}
+
+/// The branch checked out if the repository containing `dir` has a detached `HEAD`,
+/// as CI servers usually do, resolved from the [`BRANCH_VARS`].
+pub(crate) fn detached_branch(dir: &Path) -> Option<String> {
+    let repo = Repository::discover(dir)?;
+    if repo.head_ref().is_some() {
+        return None;
+    }
+    branch_from(|name| env::var(name).ok())
+}
+
+/// The first branch found in the [`BRANCH_VARS`]. Full refs other than branches,
+/// e.g. `refs/tags/v1.0.0` or `refs/pull/1/merge`, and tag names in GitLab
+/// tag pipelines are skipped.
+fn branch_from<F>(var: F) -> Option<String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let gitlab_tag = matches!(var(GITLAB_TAG_VAR), Some(tag) if !tag.trim().is_empty());
+    BRANCH_VARS.iter().find_map(|name| {
+        if gitlab_tag && *name == "CI_COMMIT_REF_NAME" {
+            return None;
+        }
+        let value = var(name)?;
+        let value = value.trim();
+        if value.is_empty() {
+            None
+        } else if value.starts_with("refs/") {
+            value.strip_prefix("refs/heads/").map(str::to_owned)
+        } else {
+            Some(value.to_owned())
+        }
+    })
+}
+
+/// Checks that the repository of `target` has the history GitVersion needs, failing
+/// with [`Error::ShallowClone`] unless `/allowshallow` is part of `args`, and warns
+/// if the branch is unknown due to a detached `HEAD`.
+pub(crate) fn check_checkout(
+    target: &Target,
+    args: &[OsString],
+    sink: &mut dyn Sink,
+) -> Result<()> {
+    let repo = match Repository::discover(&target.dir) {
+        Some(repo) => repo,
+        None => return Ok(()),
+    };
+    let allow_shallow = args
+        .iter()
+        .any(|arg| arg.to_string_lossy().eq_ignore_ascii_case("/allowshallow"));
+    if repo.is_shallow() && !allow_shallow {
+        return Err(Error::ShallowClone(repo.work_dir));
+    }
+    if repo.head_ref().is_none() && target.branch.is_none() && target.commit.is_none() {
+        emit::warning(
+            sink,
+            &format!(
+                "HEAD is detached and none of {} names a branch; \
+                 GitVersion will use \"(no branch)\". Set GitVersionBuild::branch to choose one.",
+                BRANCH_VARS.join(", ")
+            ),
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emit::Discard;
+    use std::collections::HashMap;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn branch(vars: &[(&str, &str)]) -> Option<String> {
+        let vars: HashMap<&str, &str> = vars.iter().copied().collect();
+        branch_from(|name| vars.get(name).map(|value| value.to_string()))
+    }
+
+    #[test]
+    pub fn resolves_branch_from_variables() {
+        assert_eq!(branch(&[]), None);
+        assert_eq!(
+            branch(&[("GITHUB_REF", "refs/heads/feature/x")]).as_deref(),
+            Some("feature/x")
+        );
+        assert_eq!(
+            branch(&[
+                ("GITHUB_HEAD_REF", "fix/y"),
+                ("GITHUB_REF", "refs/pull/1/merge")
+            ])
+            .as_deref(),
+            Some("fix/y")
+        );
+        assert_eq!(
+            branch(&[
+                ("GITHUB_HEAD_REF", ""),
+                ("GITHUB_REF", "refs/tags/v1.0.0"),
+                ("BUILD_SOURCEBRANCH", "refs/heads/main")
+            ])
+            .as_deref(),
+            Some("main")
+        );
+        assert_eq!(
+            branch(&[("CI_COMMIT_REF_NAME", "develop")]).as_deref(),
+            Some("develop")
+        );
+        assert_eq!(
+            branch(&[
+                ("CI_COMMIT_REF_NAME", "v1.0.0"),
+                ("CI_COMMIT_TAG", "v1.0.0")
+            ]),
+            None
+        );
+    }
+
+    #[test]
+    pub fn shallow_clones_are_rejected() -> Result<()> {
+        let repo = TempDir::new()?;
+        let git_dir = repo.path().join(".git");
+        fs::create_dir_all(&git_dir)?;
+        fs::write(git_dir.join("HEAD"), "ref: refs/heads/main\n")?;
+        let target = Target::new(repo.path());
+
+        check_checkout(&target, &[], &mut Discard)?;
+
+        fs::write(git_dir.join("shallow"), "0123456789abcdef\n")?;
+        match check_checkout(&target, &[], &mut Discard) {
+            Err(Error::ShallowClone(dir)) => assert_eq!(dir, repo.path()),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        check_checkout(&target, &["/allowshallow".into()], &mut Discard)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,967 @@
+//! Typed access to the `GitVersion.yml` configuration file.
+//!
+//! Only the GitVersion 5 keys are modeled. Unknown keys, invalid regular
+//! expressions and branches without a `regex` are reported as [`Diagnostic`]s
+//! carrying the line they were found on.
+
+use crate::emit::{self, Sink};
+use crate::{Error, Result};
+use regex::Regex;
+use serde::de::{Deserializer, MapAccess, Visitor};
+use serde::Deserialize;
+use serde_yaml::Mapping;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+/// The versioning mode of a branch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum VersioningMode {
+    /// Versions are only released from tags; builds carry build metadata.
+    ContinuousDelivery,
+    /// Every commit is released as a pre-release version.
+    ContinuousDeployment,
+    /// Every commit on the mainline increments the version.
+    Mainline,
+}
+
+/// The version field incremented on a branch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Increment {
+    /// The version is not incremented.
+    None,
+    /// The patch version is incremented.
+    Patch,
+    /// The minor version is incremented.
+    Minor,
+    /// The major version is incremented.
+    Major,
+    /// The increment of the source branch is used.
+    Inherit,
+}
+
+/// Whether `+semver:` commit messages affect the version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum CommitMessageIncrementMode {
+    /// All commit messages are considered.
+    Enabled,
+    /// Commit messages are ignored.
+    Disabled,
+    /// Only merge commit messages are considered.
+    MergeMessageOnly,
+}
+
+/// Selects which commits GitVersion ignores.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct IgnoreConfig {
+    /// The SHAs of ignored commits.
+    #[serde(default)]
+    pub sha: Vec<String>,
+    /// Commits before this date are ignored.
+    pub commits_before: Option<String>,
+}
+
+/// The contents of a `GitVersion.yml` file.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Config {
+    /// The scheme of the `AssemblySemVer` variable.
+    pub assembly_versioning_scheme: Option<String>,
+    /// The scheme of the `AssemblySemFileVer` variable.
+    pub assembly_file_versioning_scheme: Option<String>,
+    /// The format of the `InformationalVersion` variable.
+    pub assembly_informational_format: Option<String>,
+    /// The format of the `AssemblySemVer` variable.
+    pub assembly_versioning_format: Option<String>,
+    /// The format of the `AssemblySemFileVer` variable.
+    pub assembly_file_versioning_format: Option<String>,
+    /// The default versioning mode of all branches.
+    pub mode: Option<VersioningMode>,
+    /// The regex matching the prefix of version tags, `[vV]` by default.
+    pub tag_prefix: Option<String>,
+    /// The pre-release label used in `ContinuousDeployment` mode if the branch has none.
+    pub continuous_delivery_fallback_tag: Option<String>,
+    /// The minimum version to use.
+    #[serde(default, deserialize_with = "string_or_number")]
+    pub next_version: Option<String>,
+    /// The regex of commit messages incrementing the major version.
+    pub major_version_bump_message: Option<String>,
+    /// The regex of commit messages incrementing the minor version.
+    pub minor_version_bump_message: Option<String>,
+    /// The regex of commit messages incrementing the patch version.
+    pub patch_version_bump_message: Option<String>,
+    /// The regex of commit messages not incrementing the version.
+    pub no_bump_message: Option<String>,
+    /// The padding of the `LegacySemVerPadded` variable.
+    pub legacy_semver_padding: Option<u32>,
+    /// The padding of the `BuildMetaDataPadded` variable.
+    pub build_metadata_padding: Option<u32>,
+    /// The padding of the `CommitsSinceVersionSourcePadded` variable.
+    pub commits_since_version_source_padding: Option<u32>,
+    /// The weight of tagged versions in `WeightedPreReleaseNumber`.
+    pub tag_pre_release_weight: Option<u32>,
+    /// Whether `+semver:` commit messages affect the version.
+    pub commit_message_incrementing: Option<CommitMessageIncrementMode>,
+    /// The default increment of all branches.
+    pub increment: Option<Increment>,
+    /// The format of the `CommitDate` variable.
+    pub commit_date_format: Option<String>,
+    /// Custom merge message formats, keyed by name.
+    #[serde(default)]
+    pub merge_message_formats: BTreeMap<String, String>,
+    /// Whether to update the build number on build servers.
+    pub update_build_number: Option<bool>,
+    /// The commits to ignore.
+    pub ignore: Option<IgnoreConfig>,
+    /// The branch configurations, in the order of the file.
+    #[serde(default, deserialize_with = "ordered_branches")]
+    pub branches: Vec<(String, BranchConfig)>,
+}
+
+/// The configuration of a branch in `GitVersion.yml`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct BranchConfig {
+    /// The regex matching the names of the branch.
+    pub regex: Option<String>,
+    /// The versioning mode.
+    pub mode: Option<VersioningMode>,
+    /// The pre-release label; `useBranchName` uses the branch name.
+    pub tag: Option<String>,
+    /// The version field to increment.
+    pub increment: Option<Increment>,
+    /// Whether merging the branch does not increment the version.
+    pub prevent_increment_of_merged_branch_version: Option<bool>,
+    /// The regex extracting the pre-release number from the branch name.
+    pub tag_number_pattern: Option<String>,
+    /// Whether tags on merge targets are considered.
+    pub track_merge_target: Option<bool>,
+    /// The branches this branch can be created from.
+    pub source_branches: Option<Vec<String>>,
+    /// The branches that can be created from this branch.
+    pub is_source_branch_for: Option<Vec<String>>,
+    /// Whether release branches are considered for the version.
+    pub tracks_release_branches: Option<bool>,
+    /// Whether the branch is a release branch.
+    pub is_release_branch: Option<bool>,
+    /// Whether the branch is a mainline branch.
+    pub is_mainline: Option<bool>,
+    /// The weight added to the pre-release number in `WeightedPreReleaseNumber`.
+    pub pre_release_weight: Option<u32>,
+    /// Whether `+semver:` commit messages affect the version.
+    pub commit_message_incrementing: Option<CommitMessageIncrementMode>,
+}
+
+/// A problem found in a configuration file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// The 1-based line of the problem, if known.
+    pub line: Option<usize>,
+    /// A description of the problem.
+    pub message: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Determines how problems in `GitVersion.yml` are reported by `build()`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConfigValidation {
+    /// The file is not validated.
+    Ignore,
+    /// Problems are reported as `cargo:warning`s. This is the default.
+    #[default]
+    Warn,
+    /// Problems fail the build.
+    Deny,
+}
+
+impl ConfigValidation {
+    /// Validates the configuration file at `path`, if it exists.
+    pub(crate) fn check(self, path: &Path, sink: &mut dyn Sink) -> Result<()> {
+        if self == ConfigValidation::Ignore || !path.is_file() {
+            return Ok(());
+        }
+
+        let diagnostics = Config::validate(&fs::read_to_string(path)?);
+        if self == ConfigValidation::Deny && !diagnostics.is_empty() {
+            let messages: Vec<String> = diagnostics.iter().map(ToString::to_string).collect();
+            return Err(Error::InvalidConfig {
+                path: path.to_path_buf(),
+                message: messages.join("; "),
+            });
+        }
+        for diagnostic in diagnostics {
+            emit::warning(sink, &format_args!("{}: {}", path.display(), diagnostic));
+        }
+        Ok(())
+    }
+}
+
+/// The top-level keys GitVersion accepts in `/overrideconfig`.
+const OVERRIDABLE_KEYS: &[&str] = &[
+    "assembly-versioning-scheme",
+    "assembly-file-versioning-scheme",
+    "assembly-informational-format",
+    "assembly-versioning-format",
+    "assembly-file-versioning-format",
+    "mode",
+    "tag-prefix",
+    "continuous-delivery-fallback-tag",
+    "next-version",
+    "major-version-bump-message",
+    "minor-version-bump-message",
+    "patch-version-bump-message",
+    "no-bump-message",
+    "legacy-semver-padding",
+    "build-metadata-padding",
+    "commits-since-version-source-padding",
+    "tag-pre-release-weight",
+    "commit-message-incrementing",
+    "increment",
+    "commit-date-format",
+    "update-build-number",
+];
+
+/// Settings overriding `GitVersion.yml` for a single crate, passed to GitVersion
+/// as `/overrideconfig key=value` arguments.
+///
+/// `/overrideconfig` only supports the top-level keys; an overridden `increment` applies
+/// to all branches configured to inherit it. Branch settings such as
+/// [`branch_increment`](Self::branch_increment) are instead merged into a copy of
+/// `GitVersion.yml` passed as `/config`.
+///
+/// ```
+/// use dotnet_gitversion_build::config::{ConfigOverride, Increment};
+///
+/// let overrides = ConfigOverride::new()
+///     .tag_prefix("my-crate-v")
+///     .next_version("2.0.0")
+///     .increment(Increment::Minor)
+///     .branch_increment("main", Increment::Minor);
+/// assert_eq!(overrides.validate(), Vec::new());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfigOverride {
+    entries: Vec<(String, String)>,
+    branch_increments: Vec<(String, Increment)>,
+}
+
+impl ConfigOverride {
+    /// Creates an empty set of overrides.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Overrides `key`, e.g. `commit-date-format`, replacing a previous override of it.
+    /// Keys GitVersion does not accept are reported by [`validate`](Self::validate).
+    pub fn set<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        let (key, value) = (key.into(), value.into());
+        match self.entries.iter_mut().find(|(k, _)| *k == key) {
+            Some(entry) => entry.1 = value,
+            None => self.entries.push((key, value)),
+        }
+        self
+    }
+
+    /// Overrides the default versioning mode of all branches.
+    pub fn mode(self, mode: VersioningMode) -> Self {
+        self.set("mode", format!("{:?}", mode))
+    }
+
+    /// Overrides the regex matching the prefix of version tags.
+    pub fn tag_prefix<S: Into<String>>(self, prefix: S) -> Self {
+        self.set("tag-prefix", prefix)
+    }
+
+    /// Overrides the pre-release label used in `ContinuousDeployment` mode.
+    pub fn continuous_delivery_fallback_tag<S: Into<String>>(self, tag: S) -> Self {
+        self.set("continuous-delivery-fallback-tag", tag)
+    }
+
+    /// Overrides the minimum version to use.
+    pub fn next_version<S: Into<String>>(self, version: S) -> Self {
+        self.set("next-version", version)
+    }
+
+    /// Overrides the regex of commit messages incrementing the major version.
+    pub fn major_version_bump_message<S: Into<String>>(self, regex: S) -> Self {
+        self.set("major-version-bump-message", regex)
+    }
+
+    /// Overrides the regex of commit messages incrementing the minor version.
+    pub fn minor_version_bump_message<S: Into<String>>(self, regex: S) -> Self {
+        self.set("minor-version-bump-message", regex)
+    }
+
+    /// Overrides the regex of commit messages incrementing the patch version.
+    pub fn patch_version_bump_message<S: Into<String>>(self, regex: S) -> Self {
+        self.set("patch-version-bump-message", regex)
+    }
+
+    /// Overrides the regex of commit messages not incrementing the version.
+    pub fn no_bump_message<S: Into<String>>(self, regex: S) -> Self {
+        self.set("no-bump-message", regex)
+    }
+
+    /// Overrides the weight of tagged versions in `WeightedPreReleaseNumber`.
+    pub fn tag_pre_release_weight(self, weight: u32) -> Self {
+        self.set("tag-pre-release-weight", weight.to_string())
+    }
+
+    /// Overrides whether `+semver:` commit messages affect the version.
+    pub fn commit_message_incrementing(self, mode: CommitMessageIncrementMode) -> Self {
+        self.set("commit-message-incrementing", format!("{:?}", mode))
+    }
+
+    /// Overrides the default increment of all branches.
+    pub fn increment(self, increment: Increment) -> Self {
+        self.set("increment", format!("{:?}", increment))
+    }
+
+    /// Overrides the increment of the branch configuration `branch`, e.g. `main` or
+    /// `feature`, taking precedence over its `increment` in `GitVersion.yml`.
+    pub fn branch_increment<S: Into<String>>(mut self, branch: S, increment: Increment) -> Self {
+        let branch = branch.into();
+        match self
+            .branch_increments
+            .iter_mut()
+            .find(|(b, _)| *b == branch)
+        {
+            Some(entry) => entry.1 = increment,
+            None => self.branch_increments.push((branch, increment)),
+        }
+        self
+    }
+
+    /// Whether no overrides are set.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty() && self.branch_increments.is_empty()
+    }
+
+    /// Checks the keys and values of the overrides, returning all problems found.
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut diagnostics: Vec<Diagnostic> = self
+            .entries
+            .iter()
+            .filter(|(key, _)| !OVERRIDABLE_KEYS.contains(&key.as_str()))
+            .map(|(key, _)| Diagnostic {
+                line: None,
+                message: format!(
+                    "`{}` cannot be overridden; only the top-level keys of GitVersion.yml are supported, and branch increments using `branch_increment`",
+                    key
+                ),
+            })
+            .collect();
+        if !diagnostics.is_empty() {
+            return diagnostics;
+        }
+
+        diagnostics.extend(
+            Config::validate(&self.to_yaml())
+                .into_iter()
+                .map(|diagnostic| Diagnostic {
+                    line: None,
+                    ..diagnostic
+                }),
+        );
+        let version = Regex::new(r"^\d+(\.\d+){0,2}(-[0-9A-Za-z.-]+)?$").expect("valid regex");
+        for (key, value) in &self.entries {
+            if key == "next-version" && !version.is_match(value) {
+                diagnostics.push(Diagnostic {
+                    line: None,
+                    message: format!("invalid version in `next-version`: {:?}", value),
+                });
+            }
+        }
+        diagnostics
+    }
+
+    /// Fails with all problems found by [`validate`](Self::validate).
+    pub(crate) fn check(&self) -> Result<()> {
+        let diagnostics = self.validate();
+        if diagnostics.is_empty() {
+            return Ok(());
+        }
+        let messages: Vec<String> = diagnostics.iter().map(ToString::to_string).collect();
+        Err(Error::InvalidOverride(messages.join("; ")))
+    }
+
+    /// The overrides as a configuration, for applying them without GitVersion.
+    #[cfg(any(test, feature = "native"))]
+    pub(crate) fn to_config(&self) -> Result<Config> {
+        let mut config = Config::from_yaml(&self.to_yaml())
+            .map_err(|diagnostic| Error::InvalidOverride(diagnostic.message))?;
+        config.branches = self
+            .branch_increments
+            .iter()
+            .map(|(branch, increment)| {
+                let branch_config = BranchConfig {
+                    increment: Some(*increment),
+                    ..BranchConfig::default()
+                };
+                (branch.clone(), branch_config)
+            })
+            .collect();
+        Ok(config)
+    }
+
+    /// The branch overrides as `branches.<name>.increment=<increment>` entries,
+    /// for keying cached output.
+    pub(crate) fn branch_entries(&self) -> Vec<OsString> {
+        self.branch_increments
+            .iter()
+            .map(|(branch, increment)| {
+                format!("branches.{}.increment={:?}", branch, increment).into()
+            })
+            .collect()
+    }
+
+    /// Writes `base`, the configuration file in effect, merged with the branch overrides
+    /// to `path`, returning `false` without writing if there are no branch overrides.
+    pub(crate) fn write_merged_config(&self, base: &Path, path: &Path) -> Result<bool> {
+        if self.branch_increments.is_empty() {
+            return Ok(false);
+        }
+        let invalid = |message: String| Error::InvalidConfig {
+            path: base.to_path_buf(),
+            message,
+        };
+        let yaml = match fs::read_to_string(base) {
+            Ok(yaml) => yaml,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let mut config = match yaml.trim().is_empty() {
+            true => Mapping::new(),
+            false => serde_yaml::from_str(&yaml).map_err(|e| invalid(e.to_string()))?,
+        };
+
+        let branches = config
+            .entry("branches".into())
+            .or_insert_with(|| Mapping::new().into());
+        for (branch, increment) in &self.branch_increments {
+            let branch = branches
+                .as_mapping_mut()
+                .ok_or_else(|| invalid("`branches` is not a mapping".into()))?
+                .entry(branch.as_str().into())
+                .or_insert_with(|| Mapping::new().into());
+            // A branch configured as `feature: ~` has no settings yet.
+            if branch.is_null() {
+                *branch = Mapping::new().into();
+            }
+            branch
+                .as_mapping_mut()
+                .ok_or_else(|| invalid("branch configurations must be mappings".into()))?
+                .insert("increment".into(), format!("{:?}", increment).into());
+        }
+        let yaml = serde_yaml::to_string(&config).map_err(|e| invalid(e.to_string()))?;
+        fs::write(path, yaml)?;
+        Ok(true)
+    }
+
+    /// The `/overrideconfig key=value` arguments.
+    pub(crate) fn args(&self) -> Vec<OsString> {
+        self.entries
+            .iter()
+            .flat_map(|(key, value)| {
+                vec![
+                    OsString::from("/overrideconfig"),
+                    format!("{}={}", key, value).into(),
+                ]
+            })
+            .collect()
+    }
+
+    fn to_yaml(&self) -> String {
+        self.entries
+            .iter()
+            .map(|(key, value)| {
+                // Plain words and numbers keep their YAML type; everything else is a string.
+                if !value.is_empty() && value.chars().all(|c| c.is_ascii_alphanumeric()) {
+                    format!("{}: {}\n", key, value)
+                } else {
+                    format!("{}: '{}'\n", key, value.replace('\'', "''"))
+                }
+            })
+            .collect()
+    }
+}
+
+impl Config {
+    /// Reads `path`, using the defaults if the file does not exist.
+    pub fn load(path: &Path) -> Result<Self> {
+        let yaml = match fs::read_to_string(path) {
+            Ok(yaml) => yaml,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+        Self::from_yaml(&yaml).map_err(|diagnostic| Error::InvalidConfig {
+            path: path.to_path_buf(),
+            message: diagnostic.to_string(),
+        })
+    }
+
+    /// Parses the YAML contents of a configuration file.
+    pub fn from_yaml(yaml: &str) -> Result<Self, Diagnostic> {
+        // An empty file is a valid configuration.
+        if yaml.trim().is_empty() {
+            return Ok(Self::default());
+        }
+        serde_yaml::from_str(yaml).map_err(|e| Diagnostic {
+            line: e.location().map(|l| l.line()),
+            message: e.to_string(),
+        })
+    }
+
+    /// Parses and validates the YAML contents of a configuration file,
+    /// returning all problems found.
+    pub fn validate(yaml: &str) -> Vec<Diagnostic> {
+        if yaml.trim().is_empty() {
+            return Vec::new();
+        }
+
+        let lines = key_lines(yaml);
+        let line_of = |path: &str| lines.iter().find(|(p, _)| p == path).map(|(_, l)| *l);
+        let mut diagnostics = Vec::new();
+
+        let mut unknown = Vec::new();
+        let deserializer = serde_yaml::Deserializer::from_str(yaml);
+        let config: Config =
+            match serde_ignored::deserialize(deserializer, |path| unknown.push(path.to_string())) {
+                Ok(config) => config,
+                Err(e) => {
+                    return vec![Diagnostic {
+                        line: e.location().map(|l| l.line()),
+                        message: e.to_string(),
+                    }]
+                }
+            };
+
+        for path in unknown {
+            diagnostics.push(Diagnostic {
+                line: line_of(&path),
+                message: format!("unknown key `{}`", path),
+            });
+        }
+
+        let mut check_regex = |path: String, pattern: Option<&String>| {
+            if let Some(Err(e)) = pattern.map(|p| Regex::new(p)) {
+                diagnostics.push(Diagnostic {
+                    line: line_of(&path),
+                    message: format!("invalid regex in `{}`: {}", path, e),
+                });
+            }
+        };
+        check_regex("tag-prefix".into(), config.tag_prefix.as_ref());
+        check_regex(
+            "major-version-bump-message".into(),
+            config.major_version_bump_message.as_ref(),
+        );
+        check_regex(
+            "minor-version-bump-message".into(),
+            config.minor_version_bump_message.as_ref(),
+        );
+        check_regex(
+            "patch-version-bump-message".into(),
+            config.patch_version_bump_message.as_ref(),
+        );
+        check_regex("no-bump-message".into(), config.no_bump_message.as_ref());
+        for (name, branch) in &config.branches {
+            check_regex(format!("branches.{}.regex", name), branch.regex.as_ref());
+            check_regex(
+                format!("branches.{}.tag-number-pattern", name),
+                branch.tag_number_pattern.as_ref(),
+            );
+        }
+
+        let effective = config.effective_branches();
+        for (name, branch) in &effective {
+            let path = format!("branches.{}", name);
+            if branch.regex.is_none() {
+                diagnostics.push(Diagnostic {
+                    line: line_of(&path),
+                    message: format!("branch `{}` is missing the required `regex`", name),
+                });
+            }
+
+            let related = branch
+                .source_branches
+                .iter()
+                .chain(&branch.is_source_branch_for);
+            for source in related.flatten() {
+                if !effective.iter().any(|(n, _)| n == source) {
+                    diagnostics.push(Diagnostic {
+                        line: line_of(&path),
+                        message: format!("branch `{}` refers to unknown branch `{}`", name, source),
+                    });
+                }
+            }
+        }
+
+        diagnostics
+    }
+
+    /// Replaces the top-level values with those set in `other`.
+    #[cfg(feature = "native")]
+    pub(crate) fn overlay(&mut self, other: &Config) {
+        macro_rules! overlay {
+            ($($field:ident),*) => {
+                $(if other.$field.is_some() {
+                    self.$field = other.$field.clone();
+                })*
+            };
+        }
+        overlay!(
+            assembly_versioning_scheme,
+            assembly_file_versioning_scheme,
+            assembly_informational_format,
+            assembly_versioning_format,
+            assembly_file_versioning_format,
+            mode,
+            tag_prefix,
+            continuous_delivery_fallback_tag,
+            next_version,
+            major_version_bump_message,
+            minor_version_bump_message,
+            patch_version_bump_message,
+            no_bump_message,
+            legacy_semver_padding,
+            build_metadata_padding,
+            commits_since_version_source_padding,
+            tag_pre_release_weight,
+            commit_message_incrementing,
+            increment,
+            commit_date_format,
+            update_build_number
+        );
+        for (name, config) in &other.branches {
+            match self.branches.iter_mut().find(|(n, _)| n == name) {
+                Some((_, existing)) => existing.overlay(config),
+                None => self.branches.push((name.clone(), config.clone())),
+            }
+        }
+    }
+
+    /// The effective configuration of the branches, i.e. GitVersion's defaults
+    /// overlaid with the configured values, in matching order.
+    pub fn effective_branches(&self) -> Vec<(String, BranchConfig)> {
+        let mut branches = default_branches();
+        for (name, config) in &self.branches {
+            match branches.iter_mut().find(|(n, _)| n == name) {
+                Some((_, existing)) => existing.overlay(config),
+                None => branches.push((name.clone(), config.clone())),
+            }
+        }
+        branches
+    }
+}
+
+impl BranchConfig {
+    fn overlay(&mut self, other: &BranchConfig) {
+        macro_rules! overlay {
+            ($($field:ident),*) => {
+                $(if other.$field.is_some() {
+                    self.$field = other.$field.clone();
+                })*
+            };
+        }
+        overlay!(
+            regex,
+            mode,
+            tag,
+            increment,
+            prevent_increment_of_merged_branch_version,
+            tag_number_pattern,
+            track_merge_target,
+            source_branches,
+            is_source_branch_for,
+            tracks_release_branches,
+            is_release_branch,
+            is_mainline,
+            pre_release_weight,
+            commit_message_incrementing
+        );
+    }
+}
+
+/// The branch defaults of GitVersion 5.
+fn default_branches() -> Vec<(String, BranchConfig)> {
+    use Increment::{Inherit, Minor, Patch};
+    // name, regex, tag, increment, pre-release-weight, is-release-branch, is-mainline
+    let defaults = [
+        ("master", "^master$|^main$", "", Patch, 55000, false, true),
+        (
+            "develop",
+            "^dev(elop)?(ment)?$",
+            "alpha",
+            Minor,
+            0,
+            false,
+            false,
+        ),
+        (
+            "release",
+            "^releases?[/-]",
+            "beta",
+            Increment::None,
+            30000,
+            true,
+            false,
+        ),
+        (
+            "feature",
+            "^features?[/-]",
+            "useBranchName",
+            Inherit,
+            30000,
+            false,
+            false,
+        ),
+        (
+            "pull-request",
+            "^(pull|pull\\-requests|pr)[/-]",
+            "PullRequest",
+            Inherit,
+            30000,
+            false,
+            false,
+        ),
+        (
+            "hotfix",
+            "^hotfix(es)?[/-]",
+            "beta",
+            Patch,
+            30000,
+            false,
+            false,
+        ),
+        ("support", "^support[/-]", "", Patch, 55000, false, true),
+    ];
+
+    defaults
+        .iter()
+        .map(
+            |&(name, regex, tag, increment, weight, release, mainline)| {
+                let develop = name == "develop";
+                let config = BranchConfig {
+                    regex: Some(regex.into()),
+                    mode: Some(if develop {
+                        VersioningMode::ContinuousDeployment
+                    } else {
+                        VersioningMode::ContinuousDelivery
+                    }),
+                    tag: Some(tag.into()),
+                    increment: Some(increment),
+                    prevent_increment_of_merged_branch_version: Some(mainline || release),
+                    track_merge_target: Some(develop),
+                    tracks_release_branches: Some(develop),
+                    is_release_branch: Some(release),
+                    is_mainline: Some(mainline),
+                    pre_release_weight: Some(weight),
+                    ..BranchConfig::default()
+                };
+                (name.to_owned(), config)
+            },
+        )
+        .collect()
+}
+
+/// Maps dotted key paths such as `branches.master.regex` to their 1-based line,
+/// assuming the block style used by GitVersion configuration files.
+fn key_lines(yaml: &str) -> Vec<(String, usize)> {
+    let mut stack: Vec<(usize, String)> = Vec::new();
+    let mut lines = Vec::new();
+    for (index, line) in yaml.lines().enumerate() {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with('-') {
+            continue;
+        }
+        let key = match trimmed.find(':') {
+            Some(pos) => trimmed[..pos].trim().trim_matches(&['\'', '"'][..]),
+            None => continue,
+        };
+
+        let indent = line.len() - trimmed.len();
+        while matches!(stack.last(), Some((i, _)) if *i >= indent) {
+            stack.pop();
+        }
+        stack.push((indent, key.to_owned()));
+
+        let path: Vec<&str> = stack.iter().map(|(_, k)| k.as_str()).collect();
+        lines.push((path.join("."), index + 1));
+    }
+    lines
+}
+
+/// Accepts `next-version: 1.2` as well as `next-version: "1.2.0"`.
+fn string_or_number<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    Ok(
+        match Option::<serde_yaml::Value>::deserialize(deserializer)? {
+            Some(serde_yaml::Value::String(s)) => Some(s),
+            Some(serde_yaml::Value::Number(n)) => Some(n.to_string()),
+            _ => None,
+        },
+    )
+}
+
+/// Deserializes the `branches` mapping, keeping the order of the file.
+fn ordered_branches<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<(String, BranchConfig)>, D::Error> {
+    struct BranchesVisitor;
+
+    impl<'de> Visitor<'de> for BranchesVisitor {
+        type Value = Vec<(String, BranchConfig)>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a mapping of branch names to branch configurations")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut branches = Vec::new();
+            while let Some(entry) = map.next_entry()? {
+                branches.push(entry);
+            }
+            Ok(branches)
+        }
+    }
+
+    deserializer.deserialize_map(BranchesVisitor)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn repository_config_is_valid() {
+        let yaml = include_str!("../../GitVersion.yml");
+        assert_eq!(Config::validate(yaml), Vec::new());
+
+        let config = Config::from_yaml(yaml).expect("valid config");
+        let (name, master) = &config.branches[0];
+        assert_eq!(name, "master");
+        assert_eq!(master.mode, Some(VersioningMode::ContinuousDelivery));
+        assert_eq!(master.increment, Some(Increment::Patch));
+        assert_eq!(
+            master.prevent_increment_of_merged_branch_version,
+            Some(true)
+        );
+    }
+
+    #[test]
+    pub fn problems_are_reported_with_lines() {
+        let yaml = "mode: ContinuousDelivery
+tag-prefix: '[vV'
+branches:
+  develop:
+    tag: unstable
+    increase: Minor
+  custom:
+    tag: custom
+";
+        let diagnostics = Config::validate(yaml);
+        let messages: Vec<String> = diagnostics.iter().map(ToString::to_string).collect();
+        assert_eq!(diagnostics.len(), 3, "{:?}", messages);
+        assert!(messages[0].starts_with("line 6: unknown key `branches.develop.increase`"));
+        assert!(messages[1].starts_with("line 2: invalid regex in `tag-prefix`"));
+        assert!(messages[2].starts_with("line 7: branch `custom` is missing"));
+    }
+
+    #[test]
+    pub fn type_errors_are_reported_with_lines() {
+        let diagnostics = Config::validate("branches:\n  master:\n    increment: Twice\n");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].line, Some(3));
+    }
+
+    #[test]
+    pub fn overrides_are_validated() {
+        let overrides = ConfigOverride::new()
+            .tag_prefix("[vV")
+            .next_version("two")
+            .increment(Increment::Minor);
+        let messages: Vec<String> = overrides
+            .validate()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(messages.len(), 2, "{:?}", messages);
+        assert!(messages[0].starts_with("invalid regex in `tag-prefix`"));
+        assert!(messages[1].starts_with("invalid version in `next-version`"));
+
+        let branch = ConfigOverride::new().set("branches.main.increment", "Minor");
+        assert_eq!(branch.validate().len(), 1);
+    }
+
+    #[test]
+    pub fn overrides_become_arguments() -> Result<()> {
+        let overrides = ConfigOverride::new()
+            .tag_prefix("crate-v")
+            .increment(Increment::Patch)
+            .tag_pre_release_weight(100)
+            .increment(Increment::Major);
+        assert_eq!(
+            overrides.args(),
+            vec![
+                "/overrideconfig",
+                "tag-prefix=crate-v",
+                "/overrideconfig",
+                "increment=Major",
+                "/overrideconfig",
+                "tag-pre-release-weight=100",
+            ]
+        );
+
+        let config = overrides.to_config()?;
+        assert_eq!(config.tag_prefix.as_deref(), Some("crate-v"));
+        assert_eq!(config.increment, Some(Increment::Major));
+        assert_eq!(config.tag_pre_release_weight, Some(100));
+        Ok(())
+    }
+
+    #[test]
+    pub fn branch_increment_beats_configuration_file() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let base = dir.path().join("GitVersion.yml");
+        fs::write(
+            &base,
+            "tag-prefix: v\nbranches:\n  main:\n    increment: Patch\n    tag: ''\n  feature: ~\n",
+        )?;
+        let overrides = ConfigOverride::new()
+            .branch_increment("main", Increment::Minor)
+            .branch_increment("feature", Increment::Major);
+        assert!(overrides.args().is_empty());
+        assert_eq!(overrides.branch_entries().len(), 2);
+
+        let merged = dir.path().join("merged.yml");
+        assert!(overrides.write_merged_config(&base, &merged)?);
+        let config = Config::load(&merged)?;
+        assert_eq!(config.tag_prefix.as_deref(), Some("v"));
+        let branch = |config: &Config, name: &str| {
+            config
+                .branches
+                .iter()
+                .find(|(n, _)| n == name)
+                .and_then(|(_, c)| c.increment)
+        };
+        assert_eq!(branch(&config, "main"), Some(Increment::Minor));
+        assert_eq!(branch(&config, "feature"), Some(Increment::Major));
+
+        assert!(!ConfigOverride::new().write_merged_config(&base, &merged)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,185 @@
+use crate::emit::{self, Directive, Sink};
+use std::env;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+/// The file in `OUT_DIR` the diagnostics of the last GitVersion run are written to.
+pub const LOG_FILE: &str = "gitversion.log";
+
+/// The file in `OUT_DIR` GitVersion writes its log to when a [`Verbosity`] is set.
+const TOOL_LOG_FILE: &str = "gitversion-tool.log";
+
+/// The verbosity of GitVersion's log, passed as `/verbosity` along with `/l`.
+///
+/// Warnings and errors of the log are forwarded as `cargo:warning` lines, and the log
+/// is included in the `gitversion.log` written to `OUT_DIR`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verbosity {
+    /// Logs errors only.
+    Quiet,
+    /// Logs errors and warnings.
+    Minimal,
+    /// Logs informational messages as well; GitVersion's default.
+    Normal,
+    /// Logs details of the calculation as well.
+    Verbose,
+    /// Logs everything, including debug output.
+    Diagnostic,
+}
+
+impl Display for Verbosity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Verbosity::Quiet => "Quiet",
+            Verbosity::Minimal => "Minimal",
+            Verbosity::Normal => "Normal",
+            Verbosity::Verbose => "Verbose",
+            Verbosity::Diagnostic => "Diagnostic",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The diagnostic output of a GitVersion run.
+#[derive(Debug, Default)]
+pub(crate) struct Diagnostics {
+    /// The command line that was run.
+    pub command: String,
+    /// The exit status, or why there is none.
+    pub status: String,
+    /// The output GitVersion wrote to stderr.
+    pub stderr: String,
+    /// The log written due to `/l`, if any.
+    pub tool_log: Option<String>,
+}
+
+impl Diagnostics {
+    /// The path GitVersion is asked to write its log to: in `OUT_DIR`, or outside of
+    /// build scripts, e.g. in `cargo gitversion`, in the temporary directory.
+    pub(crate) fn tool_log_path() -> PathBuf {
+        match env::var_os("OUT_DIR") {
+            Some(dir) => PathBuf::from(dir).join(TOOL_LOG_FILE),
+            None => env::temp_dir().join(format!("gitversion-tool-{}.log", process::id())),
+        }
+    }
+
+    /// Reads the log GitVersion wrote to [`tool_log_path`](Self::tool_log_path).
+    /// In `OUT_DIR`, the file is removed, as [`write_log`](Self::write_log) includes it;
+    /// otherwise it is kept and its path reported to `sink`.
+    pub(crate) fn take_tool_log(sink: &mut dyn Sink) -> Option<String> {
+        let path = Self::tool_log_path();
+        let log = fs::read_to_string(&path).ok();
+        if env::var_os("OUT_DIR").is_some() {
+            let _ = fs::remove_file(&path);
+        } else if log.is_some() {
+            emit::warning(
+                sink,
+                &format_args!("GitVersion wrote its log to {}", path.display()),
+            );
+        }
+        log
+    }
+
+    /// Prints the warnings among stderr and the log as `cargo:warning` lines.
+    pub(crate) fn forward_warnings(&self, sink: &mut dyn Sink) {
+        for line in self.warnings() {
+            sink.emit(Directive::Warning(&format!("GitVersion: {}", line)));
+        }
+    }
+
+    /// The lines of stderr not logged below the warning level, and the warnings
+    /// and errors of the log.
+    fn warnings(&self) -> Vec<&str> {
+        let stderr = self
+            .stderr
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .filter(|line| !matches!(level(line), Some("INFO") | Some("DEBUG")));
+        let log = self
+            .tool_log
+            .iter()
+            .flat_map(|log| log.lines())
+            .map(str::trim)
+            .filter(|line| matches!(level(line), Some("WARN") | Some("ERROR")));
+        stderr.chain(log).collect()
+    }
+
+    /// Writes everything to [`LOG_FILE`] in `OUT_DIR`, if set. Failures are ignored,
+    /// as the log is only meant for debugging.
+    pub(crate) fn write_log(&self) {
+        let path = match env::var_os("OUT_DIR") {
+            Some(dir) => PathBuf::from(dir).join(LOG_FILE),
+            None => return,
+        };
+        let mut log = format!(
+            "command: {}\nstatus: {}\n\n--- stderr ---\n{}\n",
+            self.command, self.status, self.stderr
+        );
+        if let Some(tool_log) = &self.tool_log {
+            log.push_str("\n--- log ---\n");
+            log.push_str(tool_log);
+        }
+        let _ = fs::write(path, log);
+    }
+}
+
+/// The level GitVersion logged a line with, e.g. `WARN` for
+/// `WARN [07/17/21 10:00:00:00] Repository is shallow`.
+fn level(line: &str) -> Option<&str> {
+    let token = line.split_whitespace().next()?;
+    match token {
+        "INFO" | "DEBUG" | "WARN" | "ERROR" => Some(token),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn forwards_only_warnings() {
+        let diagnostics = Diagnostics {
+            stderr: "INFO [10:00] Working directory: /src\nCould not read the cache\n\n".into(),
+            tool_log: Some(
+                "INFO [10:00] Begin: Calculating\nWARN [10:00] The repository is shallow\n\
+                 ERROR [10:00] Detached HEAD\n    at GitVersion.Program.Main()\n"
+                    .into(),
+            ),
+            ..Diagnostics::default()
+        };
+        assert_eq!(
+            diagnostics.warnings(),
+            vec![
+                "Could not read the cache",
+                "WARN [10:00] The repository is shallow",
+                "ERROR [10:00] Detached HEAD"
+            ]
+        );
+    }
+
+    #[test]
+    pub fn tool_log_outside_of_build_scripts_is_kept() {
+        // Tests run without `OUT_DIR`, like `cargo gitversion`.
+        assert!(env::var_os("OUT_DIR").is_none());
+        let path = Diagnostics::tool_log_path();
+        assert!(path.starts_with(env::temp_dir()));
+        fs::write(&path, "INFO [10:00] Begin\n").expect("log");
+
+        let mut lines = Vec::new();
+        let log = Diagnostics::take_tool_log(&mut lines);
+        assert_eq!(log.as_deref(), Some("INFO [10:00] Begin\n"));
+        assert_eq!(
+            lines,
+            vec![format!(
+                "cargo:warning=GitVersion wrote its log to {}",
+                path.display()
+            )]
+        );
+        assert!(path.is_file());
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,143 @@
+//! Reporting the calculated version and its inputs.
+//!
+//! Build scripts report to Cargo by printing [`Directive`]s to stdout, which is what
+//! [`GitVersionBuild::run`](crate::GitVersionBuild::run) does using the [`Cargo`] sink.
+//! Other programs, e.g. release tooling, can pass [`Discard`] or their own [`Sink`]
+//! to [`GitVersionBuild::calculate`](crate::GitVersionBuild::calculate) instead.
+
+use std::fmt::{self, Display, Formatter};
+use std::io::Write;
+use std::path::Path;
+
+/// A message about the version calculation, printed by build scripts as a Cargo directive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Directive<'a> {
+    /// A variable available to the crate through `env!`, i.e. `cargo:rustc-env`.
+    RustcEnv { name: &'a str, value: &'a str },
+    /// A `cfg` set for the crate, e.g. `gitversion_label="beta"`, i.e. `cargo:rustc-cfg`.
+    RustcCfg(&'a str),
+    /// A `cfg` declared as expected, e.g. `cfg(gitversion_dirty)`, i.e. `cargo:rustc-check-cfg`.
+    RustcCheckCfg(&'a str),
+    /// A file whose changes affect the version, i.e. `cargo:rerun-if-changed`.
+    RerunIfChanged(&'a Path),
+    /// A variable whose changes affect the version, i.e. `cargo:rerun-if-env-changed`.
+    RerunIfEnvChanged(&'a str),
+    /// A problem that does not prevent calculating the version, i.e. `cargo:warning`.
+    Warning(&'a str),
+    /// A progress message not interpreted by Cargo, e.g. the GitVersion command line.
+    Message(&'a str),
+}
+
+impl Display for Directive<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Directive::RustcEnv { name, value } => write!(f, "cargo:rustc-env={}={}", name, value),
+            Directive::RustcCfg(cfg) => write!(f, "cargo:rustc-cfg={}", cfg),
+            Directive::RustcCheckCfg(cfg) => write!(f, "cargo:rustc-check-cfg={}", cfg),
+            Directive::RerunIfChanged(path) => {
+                write!(f, "cargo:rerun-if-changed={}", path.display())
+            }
+            Directive::RerunIfEnvChanged(name) => write!(f, "cargo:rerun-if-env-changed={}", name),
+            Directive::Warning(message) => write!(f, "cargo:warning={}", message),
+            Directive::Message(message) => f.write_str(message),
+        }
+    }
+}
+
+/// Receives the [`Directive`]s of a version calculation.
+pub trait Sink {
+    fn emit(&mut self, directive: Directive<'_>);
+}
+
+/// Prints the directives to stdout for Cargo to pick up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Cargo;
+
+impl Sink for Cargo {
+    fn emit(&mut self, directive: Directive<'_>) {
+        println!("{}", directive);
+    }
+}
+
+/// Discards all directives.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Discard;
+
+impl Sink for Discard {
+    fn emit(&mut self, _directive: Directive<'_>) {}
+}
+
+/// Writes the directives line by line to a writer, e.g. a file.
+/// Write errors are ignored.
+#[derive(Debug)]
+pub struct Writer<W: Write>(pub W);
+
+impl<W: Write> Sink for Writer<W> {
+    fn emit(&mut self, directive: Directive<'_>) {
+        let _ = writeln!(self.0, "{}", directive);
+    }
+}
+
+/// Collects the directives as lines.
+impl Sink for Vec<String> {
+    fn emit(&mut self, directive: Directive<'_>) {
+        self.push(directive.to_string());
+    }
+}
+
+/// Emits a [`Directive::RustcEnv`] named `prefix` followed by `name`.
+pub(crate) fn rustc_env(sink: &mut dyn Sink, prefix: &str, name: &str, value: &dyn Display) {
+    let name = format!("{}{}", prefix, name);
+    let value = value.to_string();
+    sink.emit(Directive::RustcEnv {
+        name: &name,
+        value: &value,
+    });
+}
+
+/// Emits a [`Directive::RustcCfg`] named `name`, with `value` quoted as a string literal.
+pub(crate) fn rustc_cfg(sink: &mut dyn Sink, name: &str, value: Option<&str>) {
+    let cfg = match value {
+        Some(value) => format!("{}={:?}", name, value),
+        None => name.to_owned(),
+    };
+    sink.emit(Directive::RustcCfg(&cfg));
+}
+
+/// Emits a [`Directive::Warning`].
+pub(crate) fn warning(sink: &mut dyn Sink, message: &dyn Display) {
+    sink.emit(Directive::Warning(&message.to_string()));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn directives_are_written_as_lines() {
+        let mut out = Writer(Vec::new());
+        out.emit(Directive::RustcEnv {
+            name: "GITVERSION_MAJOR",
+            value: "1",
+        });
+        out.emit(Directive::RerunIfChanged(Path::new(".git/HEAD")));
+        out.emit(Directive::Warning("shallow clone"));
+        assert_eq!(
+            String::from_utf8(out.0).expect("UTF-8"),
+            "cargo:rustc-env=GITVERSION_MAJOR=1\n\
+             cargo:rerun-if-changed=.git/HEAD\n\
+             cargo:warning=shallow clone\n"
+        );
+
+        let mut lines = Vec::new();
+        rustc_env(&mut lines, "MY_", "SEMVER", &"1.2.3");
+        rustc_cfg(&mut lines, "gitversion_branch", Some("feature/\"x\""));
+        assert_eq!(
+            lines,
+            vec![
+                "cargo:rustc-env=MY_SEMVER=1.2.3",
+                r#"cargo:rustc-cfg=gitversion_branch="feature/\"x\"""#
+            ]
+        );
+    }
+}
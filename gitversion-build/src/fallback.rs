@@ -0,0 +1,140 @@
+use crate::emit::{self, Directive, Sink};
+use crate::gitversion::{GitVersion, VersionParts, VersionSource};
+use crate::{Error, Result};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// Determines what happens when GitVersion cannot be run, e.g. because
+/// the .NET SDK or `GitVersion.Tool` is not installed.
+///
+/// Whenever a fallback is used, a `cargo:warning` names the source and the
+/// `is_fallback` field of the generated `GitVersion` is set.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum FallbackPolicy {
+    /// Fails the build. This is the default.
+    #[default]
+    Error,
+    /// Uses the `CARGO_PKG_VERSION_MAJOR`, `_MINOR`, `_PATCH` and `_PRE` variables.
+    UseCargoPkgVersion,
+    /// Uses version `0.1.0`, GitVersion's own default base version.
+    UseStaticDefaults,
+    /// Reads the JSON output of a previous GitVersion run from a file.
+    /// Relative paths are resolved against `CARGO_MANIFEST_DIR`.
+    UseJsonFile(PathBuf),
+}
+
+impl FallbackPolicy {
+    /// Applies the policy after GitVersion failed with `error`.
+    ///
+    /// Only a missing or failing tool, or a shallow clone lacking the history to
+    /// calculate the version, triggers the fallback; malformed output is always reported.
+    pub(crate) fn apply(&self, error: Error, sink: &mut dyn Sink) -> Result<GitVersion> {
+        match error {
+            Error::ToolNotFound(_)
+            | Error::ToolFailed { .. }
+            | Error::GitFailed { .. }
+            | Error::ShallowClone(_) => {}
+            _ => return Err(error),
+        }
+
+        let (mut gv, source) = match self {
+            FallbackPolicy::Error => return Err(error),
+            FallbackPolicy::UseCargoPkgVersion => {
+                (cargo_pkg_version()?, "CARGO_PKG_VERSION".into())
+            }
+            FallbackPolicy::UseStaticDefaults => (
+                GitVersion::from(VersionParts {
+                    minor: 1,
+                    ..VersionParts::default()
+                }),
+                "static defaults".into(),
+            ),
+            FallbackPolicy::UseJsonFile(path) => {
+                let path = match env::var_os("CARGO_MANIFEST_DIR") {
+                    Some(dir) if path.is_relative() => PathBuf::from(dir).join(path),
+                    _ => path.clone(),
+                };
+                sink.emit(Directive::RerunIfChanged(&path));
+                let json = fs::read_to_string(&path)?;
+                (GitVersion::from_json(&json)?, path.display().to_string())
+            }
+        };
+
+        emit::warning(
+            sink,
+            &format_args!(
+                "GitVersion is unavailable ({}); using the version from {}",
+                error, source
+            ),
+        );
+        gv.is_fallback = true;
+        gv.source = VersionSource::Fallback;
+        Ok(gv)
+    }
+}
+
+fn cargo_pkg_version() -> Result<GitVersion> {
+    Ok(cargo_pkg_version_parts()?.into())
+}
+
+/// Reads the version of the crate being built from the `CARGO_PKG_VERSION_*` variables.
+pub(crate) fn cargo_pkg_version_parts() -> Result<VersionParts> {
+    let var = |name: &str| env::var(name).map_err(|_| Error::MissingEnvVar(name.into()));
+    let number = |name: &str| -> Result<u32> {
+        var(name)?
+            .parse()
+            .map_err(|_| Error::MissingEnvVar(name.into()))
+    };
+
+    let mut parts = VersionParts {
+        major: number("CARGO_PKG_VERSION_MAJOR")?,
+        minor: number("CARGO_PKG_VERSION_MINOR")?,
+        patch: number("CARGO_PKG_VERSION_PATCH")?,
+        ..VersionParts::default()
+    };
+    parts.set_pre_release(&var("CARGO_PKG_VERSION_PRE").unwrap_or_default());
+    Ok(parts)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emit::Discard;
+
+    #[test]
+    pub fn static_defaults_are_marked_as_fallback() -> Result<()> {
+        let gv = FallbackPolicy::UseStaticDefaults
+            .apply(Error::ToolNotFound("gv".into()), &mut Discard)?;
+        assert_eq!(gv.full_semver, "0.1.0");
+        assert!(gv.is_fallback);
+        assert_eq!(gv.source, VersionSource::Fallback);
+
+        let gv = FallbackPolicy::UseStaticDefaults
+            .apply(Error::ShallowClone(PathBuf::from(".")), &mut Discard)?;
+        assert!(gv.is_fallback);
+        Ok(())
+    }
+
+    #[test]
+    pub fn invalid_json_is_not_replaced() {
+        let error = GitVersion::from_json("{}").unwrap_err();
+        let result = FallbackPolicy::UseStaticDefaults.apply(error, &mut Discard);
+        assert!(matches!(result, Err(Error::InvalidJson { .. })));
+    }
+
+    #[test]
+    pub fn pre_release_is_split_into_label_and_number() {
+        let mut parts = VersionParts {
+            major: 1,
+            minor: 2,
+            patch: 3,
+            ..VersionParts::default()
+        };
+        parts.set_pre_release("beta.4");
+        let gv = GitVersion::from(parts);
+        assert_eq!(gv.semver, "1.2.3-beta.4");
+        assert_eq!(gv.pre_release_label, "beta");
+        assert_eq!(gv.pre_release_number, Some(4));
+    }
+}
@@ -1,8 +1,13 @@
-use serde::Deserialize;
+use crate::{Error, Result};
+use serde::{Deserialize, Serialize};
 use std::fmt::{Debug, Display, Formatter};
+use std::io::Read;
 
-#[derive(Deserialize)]
-#[allow(dead_code)]
+/// The version variables calculated by GitVersion, as returned by [`build()`](crate::build).
+///
+/// The serialized form is the JSON output of `dotnet-gitversion`; fields GitVersion 6
+/// no longer emits are omitted when `None`.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct GitVersion {
     /// The major version. Should be incremented on breaking changes.
     #[serde(rename = "Major")]
@@ -46,8 +51,12 @@ pub struct GitVersion {
     pub build_meta_data: Option<u32>,
 
     /// The `build_meta_data` padded with `0` up to `4` digits.
-    #[serde(rename = "BuildMetaDataPadded")]
-    pub build_meta_data_padded: String,
+    #[serde(
+        rename = "BuildMetaDataPadded",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub build_meta_data_padded: Option<String>,
 
     /// The `build_meta_data` suffixed with `branch_name` and `sha`.
     #[serde(rename = "FullBuildMetaData")]
@@ -62,14 +71,22 @@ pub struct GitVersion {
     pub semver: String,
 
     /// Equal to `semver`, but without a `.` separating `pre_release_label` and `pre_release_number`.
-    #[serde(rename = "LegacySemVer")]
+    #[serde(
+        rename = "LegacySemVer",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
     #[deprecated]
-    pub legacy_semver: String,
+    pub legacy_semver: Option<String>,
 
     /// Equal to `legacy_semver`, but with `pre_release_number` padded with `0` up to `4` digits.
-    #[serde(rename = "LegacySemVerPadded")]
+    #[serde(
+        rename = "LegacySemVerPadded",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
     #[deprecated]
-    pub legacy_semver_padded: String,
+    pub legacy_semver_padded: Option<String>,
 
     /// Defaults to `major.minor.0.0` to allow the assembly to be hotfixed without breaking
     /// existing applications that may be referencing it.
@@ -110,24 +127,40 @@ pub struct GitVersion {
     pub short_sha: String,
 
     /// A NuGet 2.0 compatible version number.
-    #[serde(rename = "NuGetVersionV2")]
+    #[serde(
+        rename = "NuGetVersionV2",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
     #[deprecated]
-    pub nuget_version_v2: String,
+    pub nuget_version_v2: Option<String>,
 
     /// A NuGet 1.0 compatible version number.
-    #[serde(rename = "NuGetVersion")]
+    #[serde(
+        rename = "NuGetVersion",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
     #[deprecated]
-    pub nuget_version: String,
+    pub nuget_version: Option<String>,
 
     /// A NuGet 2.0 compatible `pre_release_tag`.
-    #[serde(rename = "NuGetPreReleaseTagV2")]
+    #[serde(
+        rename = "NuGetPreReleaseTagV2",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
     #[deprecated]
-    pub nuget_prerelease_tag_v2: String,
+    pub nuget_prerelease_tag_v2: Option<String>,
 
     /// A NuGet 1.0 compatible `pre_release_tag`.
-    #[serde(rename = "NuGetPreReleaseTag")]
+    #[serde(
+        rename = "NuGetPreReleaseTag",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
     #[deprecated]
-    pub nuget_prerelease_tag: String,
+    pub nuget_prerelease_tag: Option<String>,
 
     /// The SHA of the commit used as version source.
     #[serde(rename = "VersionSourceSha")]
@@ -138,8 +171,12 @@ pub struct GitVersion {
     pub commits_since_version_source: u32,
 
     /// The `commits_since_version_source` padded with `0` up to `4` digits.
-    #[serde(rename = "CommitsSinceVersionSourcePadded")]
-    pub commits_since_version_source_padded: String,
+    #[serde(
+        rename = "CommitsSinceVersionSourcePadded",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub commits_since_version_source_padded: Option<String>,
 
     /// The ISO-8601 formatted date of the commit identified by `sha`.
     #[serde(rename = "UncommittedChanges")]
@@ -148,6 +185,277 @@ pub struct GitVersion {
     /// The number of uncommitted changes present in the repository.
     #[serde(rename = "CommitDate")]
     pub commit_date: String,
+
+    /// The version of GitVersion.Tool that calculated the version, if it was run
+    /// and its version was known or required.
+    #[serde(
+        rename = "ToolVersion",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub tool_version: Option<String>,
+
+    /// Whether the version was obtained from a fallback source instead of GitVersion.
+    /// Not compared by `==`.
+    #[serde(skip)]
+    pub is_fallback: bool,
+
+    /// Where the version was obtained from. Not compared by `==`.
+    #[serde(skip)]
+    pub source: VersionSource,
+}
+
+/// Where a [`GitVersion`] was obtained from, in the order of precedence.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VersionSource {
+    /// Built from `CARGO_PKG_VERSION` and the `.cargo_vcs_info.json` of a packaged crate.
+    VcsInfo,
+    /// Read from `DOTNET_GITVERSION_JSON` or `DOTNET_GITVERSION_JSON_FILE`.
+    Precomputed,
+    /// Reconstructed from the `GitVersion_*` variables exported by build servers.
+    BuildServer,
+    /// Read from the output of an earlier GitVersion run.
+    Cache,
+    /// Calculated by GitVersion. This is the default for deserialized values.
+    #[default]
+    Tool,
+    /// Calculated by the `native` backend.
+    Native,
+    /// Determined by the [`FallbackPolicy`](crate::FallbackPolicy).
+    Fallback,
+}
+
+impl Display for VersionSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            VersionSource::VcsInfo => ".cargo_vcs_info.json",
+            VersionSource::Precomputed => "precomputed JSON",
+            VersionSource::BuildServer => "build server variables",
+            VersionSource::Cache => "cache",
+            VersionSource::Tool => "GitVersion",
+            VersionSource::Native => "native backend",
+            VersionSource::Fallback => "fallback",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The major version of the JSON schema emitted by GitVersion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchemaVersion {
+    /// GitVersion 5.x, including the legacy, NuGet and padded fields.
+    V5,
+    /// GitVersion 6.x, which removed the legacy, NuGet and padded fields.
+    V6,
+}
+
+impl SchemaVersion {
+    /// The major version number of the schema.
+    pub fn major(self) -> u32 {
+        match self {
+            SchemaVersion::V5 => 5,
+            SchemaVersion::V6 => 6,
+        }
+    }
+}
+
+/// The primary values from which all derived [`GitVersion`] fields are formatted.
+#[derive(Default)]
+pub(crate) struct VersionParts {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+    pub pre_release_label: String,
+    pub pre_release_number: Option<u32>,
+    pub pre_release_weight: u32,
+    pub build_meta_data: Option<u32>,
+    pub branch_name: String,
+    pub sha: String,
+    pub commit_date: String,
+    pub version_source_sha: String,
+    pub commits_since_version_source: u32,
+    pub uncommitted_changes: u32,
+}
+
+impl VersionParts {
+    /// Splits a pre-release tag such as `beta.4` into its label and number.
+    pub fn set_pre_release(&mut self, tag: &str) {
+        match tag.rfind('.') {
+            Some(pos) if tag[pos + 1..].parse::<u32>().is_ok() => {
+                self.pre_release_label = tag[..pos].to_owned();
+                self.pre_release_number = tag[pos + 1..].parse().ok();
+            }
+            _ => {
+                self.pre_release_label = tag.to_owned();
+                self.pre_release_number = None;
+            }
+        }
+    }
+}
+
+impl From<VersionParts> for GitVersion {
+    #[allow(deprecated)]
+    fn from(parts: VersionParts) -> Self {
+        let major_minor_patch = format!("{}.{}.{}", parts.major, parts.minor, parts.patch);
+        let label = parts.pre_release_label;
+        let number = parts.pre_release_number;
+
+        let pre_release_tag = match (label.is_empty(), number) {
+            (true, _) => String::new(),
+            (false, Some(n)) => format!("{}.{}", label, n),
+            (false, None) => label.clone(),
+        };
+        let (legacy_tag, legacy_tag_padded) = match (label.is_empty(), number) {
+            (true, _) => (String::new(), String::new()),
+            (false, Some(n)) => (format!("{}{}", label, n), format!("{}{:04}", label, n)),
+            (false, None) => (label.clone(), label.clone()),
+        };
+
+        let escaped_branch_name = escape_branch_name(&parts.branch_name);
+        let short_sha = parts.sha.chars().take(7).collect::<String>();
+        let full_build_meta_data = {
+            let mut segments = Vec::new();
+            if let Some(meta) = parts.build_meta_data {
+                segments.push(meta.to_string());
+            }
+            if !escaped_branch_name.is_empty() {
+                segments.push(format!("Branch.{}", escaped_branch_name));
+            }
+            if !parts.sha.is_empty() {
+                segments.push(format!("Sha.{}", parts.sha));
+            }
+            segments.join(".")
+        };
+
+        let semver = format!("{}{}", major_minor_patch, with_dash(&pre_release_tag));
+        let legacy_semver = format!("{}{}", major_minor_patch, with_dash(&legacy_tag));
+        let legacy_semver_padded =
+            format!("{}{}", major_minor_patch, with_dash(&legacy_tag_padded));
+        let full_semver = match parts.build_meta_data {
+            Some(meta) => format!("{}+{}", semver, meta),
+            None => semver.clone(),
+        };
+        let informational_version = if full_build_meta_data.is_empty() {
+            semver.clone()
+        } else {
+            format!("{}+{}", semver, full_build_meta_data)
+        };
+
+        GitVersion {
+            major: parts.major,
+            minor: parts.minor,
+            patch: parts.patch,
+            pre_release_tag_with_dash: with_dash(&pre_release_tag),
+            pre_release_tag,
+            pre_release_label_with_dash: with_dash(&label),
+            pre_release_label: label,
+            pre_release_number: number,
+            weighted_pre_release_number: parts.pre_release_weight + number.unwrap_or(0),
+            build_meta_data: parts.build_meta_data,
+            build_meta_data_padded: Some(
+                parts
+                    .build_meta_data
+                    .map(|m| format!("{:04}", m))
+                    .unwrap_or_default(),
+            ),
+            full_build_meta_data,
+            semver,
+            legacy_semver: Some(legacy_semver),
+            nuget_version_v2: Some(legacy_semver_padded.to_lowercase()),
+            nuget_version: Some(legacy_semver_padded.to_lowercase()),
+            nuget_prerelease_tag_v2: Some(legacy_tag_padded.to_lowercase()),
+            nuget_prerelease_tag: Some(legacy_tag_padded.to_lowercase()),
+            legacy_semver_padded: Some(legacy_semver_padded),
+            assembly_semver: format!("{}.0", major_minor_patch),
+            assembly_sem_file_version: format!("{}.0", major_minor_patch),
+            major_minor_patch,
+            informational_version,
+            full_semver,
+            branch_name: parts.branch_name,
+            escaped_branch_name,
+            sha: parts.sha,
+            short_sha,
+            version_source_sha: parts.version_source_sha,
+            commits_since_version_source: parts.commits_since_version_source,
+            commits_since_version_source_padded: Some(format!(
+                "{:04}",
+                parts.commits_since_version_source
+            )),
+            uncommitted_changes: parts.uncommitted_changes,
+            commit_date: parts.commit_date,
+            tool_version: None,
+            is_fallback: false,
+            source: VersionSource::default(),
+        }
+    }
+}
+
+fn with_dash(value: &str) -> String {
+    if value.is_empty() {
+        String::new()
+    } else {
+        format!("-{}", value)
+    }
+}
+
+/// Replaces all characters GitVersion considers unsafe in a branch name with `-`.
+pub(crate) fn escape_branch_name(branch_name: &str) -> String {
+    branch_name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+impl GitVersion {
+    /// Detects the schema the values were read from by the presence of fields
+    /// GitVersion 6 no longer emits.
+    #[allow(deprecated)]
+    pub fn schema(&self) -> SchemaVersion {
+        if self.legacy_semver.is_some() || self.commits_since_version_source_padded.is_some() {
+            SchemaVersion::V5
+        } else {
+            SchemaVersion::V6
+        }
+    }
+
+    /// Deserializes the JSON output of `dotnet-gitversion`.
+    pub fn from_json(json: &str) -> Result<Self> {
+        Self::deserialize_from(&mut serde_json::Deserializer::from_str(json))
+    }
+
+    /// Deserializes the JSON output of `dotnet-gitversion` from `reader`,
+    /// e.g. a file written by `dotnet-gitversion /output file`.
+    pub fn from_reader<R: Read>(reader: R) -> Result<Self> {
+        Self::deserialize_from(&mut serde_json::Deserializer::from_reader(reader))
+    }
+
+    fn deserialize_from<'de, R>(deserializer: &mut serde_json::Deserializer<R>) -> Result<Self>
+    where
+        R: serde_json::de::Read<'de>,
+    {
+        serde_path_to_error::deserialize(deserializer).map_err(|e| {
+            let path = e.path().to_string();
+            let source = e.into_inner();
+            let field = if path != "." {
+                Some(path)
+            } else {
+                missing_field(&source.to_string())
+            };
+            Error::InvalidJson { field, source }
+        })
+    }
+}
+
+/// Extracts the field name from serde's "missing field `Name`" messages.
+fn missing_field(message: &str) -> Option<String> {
+    let rest = message.strip_prefix("missing field `")?;
+    rest.find('`').map(|end| rest[..end].to_owned())
 }
 
 impl Display for GitVersion {
@@ -161,3 +469,87 @@ impl Debug for GitVersion {
         write!(f, "{:?}", self.informational_version)
     }
 }
+
+/// Compares the version variables only; where the version was obtained from,
+/// i.e. [`source`](GitVersion::source) and [`is_fallback`](GitVersion::is_fallback),
+/// is ignored.
+impl PartialEq for GitVersion {
+    #[allow(deprecated)]
+    fn eq(&self, other: &Self) -> bool {
+        // Destructured so that new fields cannot be forgotten.
+        let GitVersion {
+            major,
+            minor,
+            patch,
+            pre_release_tag,
+            pre_release_tag_with_dash,
+            pre_release_label,
+            pre_release_label_with_dash,
+            pre_release_number,
+            weighted_pre_release_number,
+            build_meta_data,
+            build_meta_data_padded,
+            full_build_meta_data,
+            major_minor_patch,
+            semver,
+            legacy_semver,
+            legacy_semver_padded,
+            assembly_semver,
+            assembly_sem_file_version,
+            informational_version,
+            full_semver,
+            branch_name,
+            escaped_branch_name,
+            sha,
+            short_sha,
+            nuget_version_v2,
+            nuget_version,
+            nuget_prerelease_tag_v2,
+            nuget_prerelease_tag,
+            version_source_sha,
+            commits_since_version_source,
+            commits_since_version_source_padded,
+            uncommitted_changes,
+            commit_date,
+            tool_version,
+            is_fallback: _,
+            source: _,
+        } = self;
+        *major == other.major
+            && *minor == other.minor
+            && *patch == other.patch
+            && *pre_release_tag == other.pre_release_tag
+            && *pre_release_tag_with_dash == other.pre_release_tag_with_dash
+            && *pre_release_label == other.pre_release_label
+            && *pre_release_label_with_dash == other.pre_release_label_with_dash
+            && *pre_release_number == other.pre_release_number
+            && *weighted_pre_release_number == other.weighted_pre_release_number
+            && *build_meta_data == other.build_meta_data
+            && *build_meta_data_padded == other.build_meta_data_padded
+            && *full_build_meta_data == other.full_build_meta_data
+            && *major_minor_patch == other.major_minor_patch
+            && *semver == other.semver
+            && *legacy_semver == other.legacy_semver
+            && *legacy_semver_padded == other.legacy_semver_padded
+            && *assembly_semver == other.assembly_semver
+            && *assembly_sem_file_version == other.assembly_sem_file_version
+            && *informational_version == other.informational_version
+            && *full_semver == other.full_semver
+            && *branch_name == other.branch_name
+            && *escaped_branch_name == other.escaped_branch_name
+            && *sha == other.sha
+            && *short_sha == other.short_sha
+            && *nuget_version_v2 == other.nuget_version_v2
+            && *nuget_version == other.nuget_version
+            && *nuget_prerelease_tag_v2 == other.nuget_prerelease_tag_v2
+            && *nuget_prerelease_tag == other.nuget_prerelease_tag
+            && *version_source_sha == other.version_source_sha
+            && *commits_since_version_source == other.commits_since_version_source
+            && *commits_since_version_source_padded == other.commits_since_version_source_padded
+            && *uncommitted_changes == other.uncommitted_changes
+            && *commit_date == other.commit_date
+            && *tool_version == other.tool_version
+    }
+}
+
+impl Eq for GitVersion {}
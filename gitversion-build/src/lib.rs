@@ -1,23 +1,135 @@
+mod build_server;
+mod builder;
+mod cache;
+mod ci;
+pub mod config;
+mod diagnostics;
+pub mod emit;
+mod fallback;
 mod gitversion;
-
-use anyhow::Result;
-use gitversion::GitVersion;
+#[cfg(feature = "manifest")]
+pub mod manifest;
+#[cfg(feature = "native")]
+mod native;
+mod package_version;
+mod process;
+mod release;
+mod repository;
+mod tool;
+mod vcs_info;
+
+pub use build_server::BuildServerEnv;
+pub use builder::{
+    Backend, GitVersionBuild, ToolVersionCheck, DEFAULT_ENV_PREFIX, DEFAULT_EXECUTABLE,
+    DEFAULT_OUTPUT_FILE, JSON_ENV_VAR, JSON_FILE_ENV_VAR,
+};
+pub use cache::Cache;
+pub use diagnostics::{Verbosity, LOG_FILE};
+pub use fallback::FallbackPolicy;
+pub use gitversion::{GitVersion, SchemaVersion, VersionSource};
+pub use package_version::{PackageVersionCheck, VersionField};
+pub use release::{ReleaseCondition, ReleasePolicy};
+pub use tool::Tool;
+
+use diagnostics::Diagnostics;
+use emit::{rustc_cfg, rustc_env, Directive, Sink};
 use quote::quote;
 use std::env;
+use std::ffi::{OsStr, OsString};
 use std::fmt::Debug;
-use std::fs::File;
-use std::io::{BufWriter, Read, Write};
-use std::path::Path;
-use std::process::Command;
+use std::fs::{self, File};
+use std::io::{BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::time::Duration;
 use thiserror::Error;
 
+/// The result type used by this crate.
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("I/O error")]
     Io(#[from] std::io::Error),
 
-    #[error("environment variable is missing")]
-    MissingEnvVar,
+    #[error("environment variable {0} is missing")]
+    MissingEnvVar(String),
+
+    #[error("GitVersion could not be found (tried {0}); is GitVersion.Tool installed?")]
+    ToolNotFound(String),
+
+    #[error("GitVersion failed with {status}: {stderr}")]
+    ToolFailed { status: ExitStatus, stderr: String },
+
+    #[error("invalid GitVersion JSON{}: {source}", .field.as_ref().map(|f| format!(" at field {}", f)).unwrap_or_default())]
+    InvalidJson {
+        field: Option<String>,
+        source: serde_json::Error,
+    },
+
+    #[error("GitVersion did not finish within {timeout:?}: {stderr}")]
+    Timeout { timeout: Duration, stderr: String },
+
+    #[error("{} is a shallow clone, but the version is calculated from the full history; run `git fetch --unshallow` or check out with `fetch-depth: 0`", .0.display())]
+    ShallowClone(PathBuf),
+
+    #[error("git {args} failed: {stderr}")]
+    GitFailed { args: String, stderr: String },
+
+    #[error("invalid configuration in {}: {message}", .path.display())]
+    InvalidConfig { path: PathBuf, message: String },
+
+    #[error("GitVersion {} does not satisfy the required version {requirement}", if .version.is_empty() { "of unknown version" } else { .version.as_str() })]
+    ToolVersionMismatch {
+        version: String,
+        requirement: String,
+    },
+
+    #[error("invalid GitVersion version requirement: {0}")]
+    InvalidToolVersionRequirement(#[from] semver::Error),
+
+    #[error("invalid /overrideconfig: {0}")]
+    InvalidOverride(String),
+
+    #[error("unsupported configuration: {0}")]
+    UnsupportedConfig(String),
+
+    #[error("{variable} is {expected:?}, but the calculated version has {actual:?}")]
+    BuildServerMismatch {
+        variable: String,
+        expected: String,
+        actual: String,
+    },
+
+    #[error(
+        "CARGO_PKG_VERSION is {package_version}, but GitVersion calculated the {field} {expected}"
+    )]
+    PackageVersionMismatch {
+        package_version: String,
+        field: VersionField,
+        expected: String,
+    },
+
+    #[error("release policy violated: {}", .0.join("; "))]
+    ReleasePolicyViolated(Vec<String>),
+
+    #[error("invalid branch regex {regex:?}: {source}")]
+    InvalidBranchRegex { regex: String, source: regex::Error },
+
+    #[error("{version:?} is not a valid semantic version: {source}")]
+    InvalidVersion {
+        version: String,
+        source: semver::Error,
+    },
+
+    #[error("invalid manifest {}: {message}", .path.display())]
+    InvalidManifest { path: PathBuf, message: String },
+
+    #[error("not at version {version}: {}", .paths.iter().map(|p| p.display().to_string()).collect::<Vec<_>>().join(", "))]
+    ManifestOutOfDate {
+        version: String,
+        paths: Vec<PathBuf>,
+    },
 }
 
 fn same_content_as(path: &Path, content: &str) -> Result<bool> {
@@ -37,217 +149,433 @@ macro_rules! include_gitversion_from_path {
 
 /// Calls `dotnet-gitversion`, converts the JSON output and generates a `gitversion.rs`
 /// file in the `OUT_DIR` directory.
+///
+/// This is a shortcut for `GitVersionBuild::new().run()`; see [`GitVersionBuild`]
+/// for configuring the invocation.
 pub fn build() -> Result<GitVersion> {
-    let path = env::var_os("OUT_DIR").ok_or(Error::MissingEnvVar)?;
-    let path: &Path = path.as_ref();
-    let path = path.join("gitversion.rs");
-    write_version_file(path.as_path())
+    GitVersionBuild::new().run()
+}
+
+fn dotnet_gitversion(config: &GitVersionBuild, sink: &mut dyn Sink) -> Result<String> {
+    let tool = config.find_tool(sink)?;
+    sink.emit(Directive::Message(&format!(
+        "Calculating the version using `{}`",
+        tool
+    )));
+
+    let target = config.target()?;
+    let mut command = tool.command();
+    command
+        .args(config.get_args())
+        .args(target.tool_args()?)
+        .current_dir(&target.dir);
+    let tool_log = config.get_verbosity().map(|verbosity| {
+        let path = Diagnostics::tool_log_path();
+        command.arg("/verbosity").arg(verbosity.to_string());
+        command.arg("/l").arg(&path);
+        path
+    });
+    let mut diagnostics = Diagnostics {
+        command: format!("{:?}", command),
+        ..Diagnostics::default()
+    };
+
+    let result = process::output(&mut command, config.get_timeout());
+    if tool_log.is_some() {
+        diagnostics.tool_log = Diagnostics::take_tool_log(sink);
+    }
+    let out = match result {
+        Ok(out) => out,
+        Err(e) => {
+            diagnostics.status = e.to_string();
+            diagnostics.write_log();
+            return Err(match e {
+                Error::Io(e) if e.kind() == ErrorKind::NotFound => {
+                    Error::ToolNotFound(tool.to_string())
+                }
+                e => e,
+            });
+        }
+    };
+    diagnostics.status = out.status.to_string();
+    diagnostics.stderr = String::from_utf8_lossy(&out.stderr).into_owned();
+    diagnostics.write_log();
+
+    if !out.status.success() {
+        // GitVersion logs most of its errors to stdout.
+        let stderr = diagnostics.stderr.trim().to_owned();
+        let stderr = if stderr.is_empty() {
+            String::from_utf8_lossy(&out.stdout).trim().to_owned()
+        } else {
+            stderr
+        };
+        return Err(Error::ToolFailed {
+            status: out.status,
+            stderr,
+        });
+    }
+    diagnostics.forward_warnings(sink);
+
+    let json = String::from_utf8_lossy(&out.stdout).trim().to_owned();
+    // Querying `/version` costs another start of the tool, so only do so when it is checked.
+    let version = match tool.version() {
+        Some(version) => Some(version.to_owned()),
+        None if config.get_tool_version()?.is_some() => {
+            tool.query_version(&target.dir, config.get_timeout())?
+        }
+        None => None,
+    };
+    Ok(with_tool_version(json, version))
 }
 
-fn dotnet_gitversion() -> Option<String> {
-    Command::new("dotnet-gitversion")
-        .args(&["/nofetch"])
-        .output()
-        .ok()
-        .and_then(|out| {
-            std::str::from_utf8(&out.stdout[..])
-                .map(str::trim)
-                .map(str::to_owned)
-                .ok()
-        })
+/// Adds the `ToolVersion` to the JSON output, so that it is cached along with it.
+fn with_tool_version(json: String, version: Option<String>) -> String {
+    match (serde_json::from_str(&json), version) {
+        (Ok(serde_json::Value::Object(mut map)), Some(version)) => {
+            map.insert("ToolVersion".into(), version.into());
+            serde_json::Value::Object(map).to_string()
+        }
+        _ => json,
+    }
 }
 
-/// Write version.rs file to OUT_DIR
-#[allow(deprecated)]
-fn write_version_file(path: &Path) -> Result<GitVersion> {
-    let content = if let Some(json) = dotnet_gitversion() {
-        json.to_owned()
-    } else {
-        "{}".to_owned()
+/// Calls `dotnet-gitversion` unless its output for the current repository state is cached
+/// by a tool meeting the `tool_version` requirement. Returns the output and whether it was cached.
+fn cached_gitversion(config: &GitVersionBuild, sink: &mut dyn Sink) -> Result<(String, bool)> {
+    let target = config.target()?;
+    let target_args = target.cache_args();
+    // With executable discovery, the first of the candidates is used as the key.
+    let executable = config
+        .get_executable()
+        .map_or_else(|| OsStr::new(DEFAULT_EXECUTABLE), OsString::as_os_str);
+    let mut inputs = vec![executable];
+    inputs.extend(config.get_args().iter().map(OsString::as_os_str));
+    inputs.extend(target_args.iter().map(OsString::as_os_str));
+    let requirement = config.get_tool_version()?;
+    let is_current = |json: &str| match &requirement {
+        // The cached output may stem from a tool that has since been updated.
+        Some(requirement) => matches!(
+            GitVersion::from_json(json),
+            Ok(gv) if tool::check_version(gv.tool_version.as_deref(), requirement).is_ok()
+        ),
+        None => true,
+    };
+    config.get_cache().get_or_run(
+        &target.dir,
+        &inputs,
+        &[&target.config_path()],
+        is_current,
+        || dotnet_gitversion(config, sink),
+    )
+}
+
+/// Reads the JSON provided by [`JSON_ENV_VAR`] or [`JSON_FILE_ENV_VAR`], if set,
+/// looking up the variables using `var`.
+/// Relative file paths are resolved against `CARGO_MANIFEST_DIR`.
+fn precomputed_json<F>(
+    config: &GitVersionBuild,
+    var: F,
+    sink: &mut dyn Sink,
+) -> Result<Option<String>>
+where
+    F: Fn(&str) -> Option<OsString>,
+{
+    if let Some(json) = var(JSON_ENV_VAR).filter(|json| !json.is_empty()) {
+        return Ok(Some(json.to_string_lossy().into_owned()));
+    }
+    match var(JSON_FILE_ENV_VAR).filter(|path| !path.is_empty()) {
+        Some(path) => {
+            let path = config.manifest_dir()?.join(path);
+            sink.emit(Directive::RerunIfChanged(&path));
+            Ok(Some(fs::read_to_string(&path)?))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Calculates the version from, in order of precedence, the `.cargo_vcs_info.json` of
+/// packaged crates, precomputed JSON, the build server variables (with [`BuildServerEnv::Use`])
+/// or the configured backend.
+fn calculate_version(config: &GitVersionBuild, sink: &mut dyn Sink) -> Result<GitVersion> {
+    // The version of a published crate is fixed, regardless of the repository
+    // or environment it is built in.
+    if let Some(gv) = vcs_info::from_vcs_info(&config.manifest_dir()?)? {
+        return Ok(gv);
+    }
+
+    let mode = config.get_build_server_env();
+    let from_env = match mode {
+        BuildServerEnv::Ignore => None,
+        BuildServerEnv::Use | BuildServerEnv::Verify => build_server::from_env()?,
+    };
+
+    let gv = match precomputed_json(config, |name| env::var_os(name), sink)? {
+        Some(json) => GitVersion {
+            source: VersionSource::Precomputed,
+            ..GitVersion::from_json(&json)?
+        },
+        None => match from_env {
+            Some(gv) if mode == BuildServerEnv::Use => return Ok(gv),
+            _ => run_backend(config, sink)?,
+        },
     };
 
-    let gv: GitVersion = serde_json::from_str(content.as_str())?;
+    if let Some(from_env) = &from_env {
+        build_server::verify(from_env, &gv)?;
+    }
+    Ok(gv)
+}
+
+/// Runs GitVersion, checking its version if required.
+fn run_tool(config: &GitVersionBuild, sink: &mut dyn Sink) -> Result<GitVersion> {
+    let (json, cached) = cached_gitversion(config, sink)?;
+    let mut gv = GitVersion::from_json(&json)?;
+    if cached {
+        gv.source = VersionSource::Cache;
+    }
+    if let Some(requirement) = config.get_tool_version()? {
+        match tool::check_version(gv.tool_version.as_deref(), &requirement) {
+            Err(e) if config.get_tool_version_check() == ToolVersionCheck::Warn => {
+                emit::warning(sink, &e)
+            }
+            result => result?,
+        }
+    }
+    Ok(gv)
+}
+
+/// Calculates the version using the configured backend.
+fn run_backend(config: &GitVersionBuild, sink: &mut dyn Sink) -> Result<GitVersion> {
+    ci::check_checkout(&config.target()?, config.get_args(), sink)?;
+    #[cfg(feature = "native")]
+    {
+        let backend = config.get_backend();
+        if backend == Backend::Native {
+            return native::calculate(&config.target()?);
+        }
+        match run_tool(config, sink) {
+            Err(Error::ToolNotFound(_)) if backend == Backend::Auto => {
+                native::calculate(&config.target()?)
+            }
+            result => result,
+        }
+    }
+
+    #[cfg(not(feature = "native"))]
+    run_tool(config, sink)
+}
+
+/// The `cfg`s set by [`emit_cfgs`], declared via `cargo:rustc-check-cfg`.
+const CHECK_CFGS: &[&str] = &[
+    "cfg(gitversion_prerelease)",
+    "cfg(gitversion_dirty)",
+    "cfg(gitversion_branch, values(any()))",
+    "cfg(gitversion_label, values(any()))",
+];
+
+/// Emits the `cfg`s derived from `gv`: `gitversion_prerelease` for a non-empty
+/// `PreReleaseTag`, `gitversion_dirty` for uncommitted changes, and the non-empty
+/// `BranchName` and `PreReleaseLabel` as `gitversion_branch` and `gitversion_label`.
+fn emit_cfgs(gv: &GitVersion, sink: &mut dyn Sink) {
+    for cfg in CHECK_CFGS {
+        sink.emit(Directive::RustcCheckCfg(cfg));
+    }
+    if !gv.pre_release_tag.is_empty() {
+        rustc_cfg(sink, "gitversion_prerelease", None);
+    }
+    if gv.uncommitted_changes > 0 {
+        rustc_cfg(sink, "gitversion_dirty", None);
+    }
+    if !gv.branch_name.is_empty() {
+        rustc_cfg(sink, "gitversion_branch", Some(&gv.branch_name));
+    }
+    if !gv.pre_release_label.is_empty() {
+        rustc_cfg(sink, "gitversion_label", Some(&gv.pre_release_label));
+    }
+}
+
+/// Emits the `cargo:rustc-env` variables of `gv`, prefixed with `prefix`.
+#[allow(deprecated)]
+fn emit_version(gv: &GitVersion, prefix: &str, sink: &mut dyn Sink) {
+    rustc_env(sink, prefix, "MAJOR", &gv.major);
+    rustc_env(sink, prefix, "MINOR", &gv.minor);
+    rustc_env(sink, prefix, "PATCH", &gv.patch);
+    rustc_env(sink, prefix, "PRE_RELEASE_TAG", &gv.pre_release_tag);
+    rustc_env(
+        sink,
+        prefix,
+        "PRE_RELEASE_TAG_WITH_DASH",
+        &gv.pre_release_tag_with_dash,
+    );
+    rustc_env(sink, prefix, "PRE_RELEASE_LABEL", &gv.pre_release_label);
+    rustc_env(
+        sink,
+        prefix,
+        "PRE_RELEASE_LABEL_WITH_DASH",
+        &gv.pre_release_label_with_dash,
+    );
+    if let Some(number) = gv.pre_release_number {
+        rustc_env(sink, prefix, "PRE_RELEASE_NUMBER", &number);
+    }
+    rustc_env(
+        sink,
+        prefix,
+        "WEIGHTED_PRE_RELEASE_NUMBER",
+        &gv.weighted_pre_release_number,
+    );
+    if let Some(number) = gv.build_meta_data {
+        rustc_env(sink, prefix, "BUILD_META_DATA", &number);
+    }
+    if let Some(value) = &gv.build_meta_data_padded {
+        rustc_env(sink, prefix, "BUILD_META_DATA_PADDED", value);
+    }
+    rustc_env(
+        sink,
+        prefix,
+        "FULL_BUILD_META_DATA",
+        &gv.full_build_meta_data,
+    );
+    rustc_env(sink, prefix, "MAJOR_MINOR_PATCH", &gv.major_minor_patch);
+    rustc_env(sink, prefix, "SEMVER", &gv.semver);
+    if let Some(value) = &gv.legacy_semver {
+        rustc_env(sink, prefix, "LEGACY_SEMVER", value);
+    }
+    if let Some(value) = &gv.legacy_semver_padded {
+        rustc_env(sink, prefix, "LEGACY_SEMVER_PADDED", value);
+    }
+    rustc_env(sink, prefix, "ASSEMBLY_SEMVER", &gv.assembly_semver);
+    rustc_env(
+        sink,
+        prefix,
+        "ASSEMBLY_SEM_FILE_VERSION",
+        &gv.assembly_sem_file_version,
+    );
+    rustc_env(
+        sink,
+        prefix,
+        "INFORMATIONAL_VERSION",
+        &gv.informational_version,
+    );
+    rustc_env(sink, prefix, "FULL_SEMVER", &gv.full_semver);
+    rustc_env(sink, prefix, "BRANCH_NAME", &gv.branch_name);
+    rustc_env(sink, prefix, "ESCAPED_BRANCH_NAME", &gv.escaped_branch_name);
+    rustc_env(sink, prefix, "SHA", &gv.sha);
+    rustc_env(sink, prefix, "SHORT_SHA", &gv.short_sha);
+    if let Some(value) = &gv.nuget_version_v2 {
+        rustc_env(sink, prefix, "NUGET_VERSION_V2", value);
+    }
+    if let Some(value) = &gv.nuget_version {
+        rustc_env(sink, prefix, "NUGET_VERSION", value);
+    }
+    if let Some(value) = &gv.nuget_prerelease_tag_v2 {
+        rustc_env(sink, prefix, "NUGET_PRERELEASE_TAG_V2", value);
+    }
+    if let Some(value) = &gv.nuget_prerelease_tag {
+        rustc_env(sink, prefix, "NUGET_PRERELEASE_TAG", value);
+    }
+    rustc_env(sink, prefix, "VERSION_SOURCE_SHA", &gv.version_source_sha);
+    rustc_env(
+        sink,
+        prefix,
+        "COMMITS_SINCE_VERSION_SOURCE",
+        &gv.commits_since_version_source,
+    );
+    if let Some(value) = &gv.commits_since_version_source_padded {
+        rustc_env(sink, prefix, "COMMITS_SINCE_VERSION_SOURCE_PADDED", value);
+    }
+    rustc_env(sink, prefix, "UNCOMMITTED_CHANGES", &gv.uncommitted_changes);
+    rustc_env(sink, prefix, "COMMIT_DATE", &gv.commit_date);
+    rustc_env(sink, prefix, "IS_FALLBACK", &gv.is_fallback);
+    if let Some(version) = &gv.tool_version {
+        rustc_env(sink, prefix, "TOOL_VERSION", version);
+    }
+    rustc_env(sink, prefix, "SCHEMA_VERSION", &gv.schema().major());
+}
 
+/// Write version.rs file to OUT_DIR
+#[allow(deprecated)]
+fn write_version_file(path: &Path, gv: &GitVersion) -> Result<()> {
     let major = gv.major;
     let minor = gv.minor;
     let patch = gv.patch;
-    println!("cargo:rustc-env=GITVERSION_MAJOR={}", major.to_string());
-    println!("cargo:rustc-env=GITVERSION_MINOR={}", minor.to_string());
-    println!("cargo:rustc-env=GITVERSION_PATCH={}", patch.to_string());
 
     let pre_release_tag = gv.pre_release_tag.clone();
     let pre_release_tag_with_dash = gv.pre_release_tag_with_dash.clone();
-    println!(
-        "cargo:rustc-env=GITVERSION_PRE_RELEASE_TAG={}",
-        pre_release_tag.clone()
-    );
-    println!(
-        "cargo:rustc-env=GITVERSION_PRE_RELEASE_TAG_WITH_DASH={}",
-        pre_release_tag_with_dash.clone()
-    );
 
     let pre_release_label = gv.pre_release_label.clone();
     let pre_release_label_with_dash = gv.pre_release_label_with_dash.clone();
-    println!(
-        "cargo:rustc-env=GITVERSION_PRE_RELEASE_LABEL={}",
-        pre_release_label.clone()
-    );
-    println!(
-        "cargo:rustc-env=GITVERSION_PRE_RELEASE_LABEL_WITH_DASH={}",
-        pre_release_label_with_dash.clone()
-    );
 
-    let has_pre_release_number = gv.pre_release_number != None;
+    let has_pre_release_number = gv.pre_release_number.is_some();
     let pre_release_number = gv.pre_release_number.unwrap_or(0);
-    if let Some(number) = gv.pre_release_number {
-        println!(
-            "cargo:rustc-env=GITVERSION_PRE_RELEASE_NUMBER={}",
-            number.to_string()
-        );
-    }
 
     let weighted_pre_release_number = gv.weighted_pre_release_number;
-    println!(
-        "cargo:rustc-env=GITVERSION_WEIGHTED_PRE_RELEASE_NUMBER={}",
-        weighted_pre_release_number.to_string()
-    );
 
-    let has_build_meta_data = gv.build_meta_data != None;
+    let has_build_meta_data = gv.build_meta_data.is_some();
     let build_meta_data = gv.build_meta_data.unwrap_or(0);
-    if let Some(number) = gv.build_meta_data {
-        println!(
-            "cargo:rustc-env=GITVERSION_BUILD_META_DATA={}",
-            number.to_string()
-        );
-    }
 
-    let build_meta_data_padded = gv.build_meta_data_padded.clone();
-    println!(
-        "cargo:rustc-env=GITVERSION_BUILD_META_DATA_PADDED={}",
-        build_meta_data_padded.clone()
-    );
+    let has_build_meta_data_padded = gv.build_meta_data_padded.is_some();
+    let build_meta_data_padded = gv.build_meta_data_padded.clone().unwrap_or_default();
 
     let full_build_meta_data = gv.full_build_meta_data.clone();
-    println!(
-        "cargo:rustc-env=GITVERSION_FULL_BUILD_META_DATA={}",
-        full_build_meta_data.clone()
-    );
 
     let major_minor_patch = gv.major_minor_patch.clone();
-    println!(
-        "cargo:rustc-env=GITVERSION_MAJOR_MINOR_PATCH={}",
-        major_minor_patch.clone()
-    );
 
     let semver = gv.semver.clone();
-    println!("cargo:rustc-env=GITVERSION_SEMVER={}", semver.clone());
 
-    let legacy_semver = gv.legacy_semver.clone();
-    let legacy_semver_padded = gv.legacy_semver_padded.clone();
-    println!(
-        "cargo:rustc-env=GITVERSION_LEGACY_SEMVER={}",
-        legacy_semver.clone()
-    );
-    println!(
-        "cargo:rustc-env=GITVERSION_LEGACY_SEMVER_PADDED={}",
-        legacy_semver_padded.clone()
-    );
+    let has_legacy_semver = gv.legacy_semver.is_some();
+    let legacy_semver = gv.legacy_semver.clone().unwrap_or_default();
+
+    let has_legacy_semver_padded = gv.legacy_semver_padded.is_some();
+    let legacy_semver_padded = gv.legacy_semver_padded.clone().unwrap_or_default();
 
     let assembly_semver = gv.assembly_semver.clone();
-    println!(
-        "cargo:rustc-env=GITVERSION_ASSEMBLY_SEMVER={}",
-        assembly_semver.clone()
-    );
 
     let assembly_sem_file_version = gv.assembly_sem_file_version.clone();
-    println!(
-        "cargo:rustc-env=GITVERSION_ASSEMBLY_SEM_FILE_VERSION={}",
-        assembly_sem_file_version.clone()
-    );
 
     let informational_version = gv.informational_version.clone();
-    println!(
-        "cargo:rustc-env=GITVERSION_INFORMATIONAL_VERSION={}",
-        informational_version.clone()
-    );
 
     let full_semver = gv.full_semver.clone();
-    println!(
-        "cargo:rustc-env=GITVERSION_FULL_SEMVER={}",
-        full_semver.clone()
-    );
 
     let branch_name = gv.branch_name.clone();
-    println!(
-        "cargo:rustc-env=GITVERSION_BRANCH_NAME={}",
-        branch_name.clone()
-    );
 
     let escaped_branch_name = gv.escaped_branch_name.clone();
-    println!(
-        "cargo:rustc-env=GITVERSION_ESCAPED_BRANCH_NAME={}",
-        escaped_branch_name.clone()
-    );
 
     let sha = gv.sha.clone();
-    println!("cargo:rustc-env=GITVERSION_SHA={}", sha.clone());
 
     let short_sha = gv.short_sha.clone();
-    println!("cargo:rustc-env=GITVERSION_SHORT_SHA={}", short_sha.clone());
 
-    let nuget_version_v2 = gv.nuget_version_v2.clone();
-    println!(
-        "cargo:rustc-env=GITVERSION_NUGET_VERSION_V2={}",
-        nuget_version_v2.clone()
-    );
+    let has_nuget_version_v2 = gv.nuget_version_v2.is_some();
+    let nuget_version_v2 = gv.nuget_version_v2.clone().unwrap_or_default();
 
-    let nuget_version = gv.nuget_version.clone();
-    println!(
-        "cargo:rustc-env=GITVERSION_NUGET_VERSION={}",
-        nuget_version.clone()
-    );
+    let has_nuget_version = gv.nuget_version.is_some();
+    let nuget_version = gv.nuget_version.clone().unwrap_or_default();
 
-    let nuget_prerelease_tag_v2 = gv.nuget_prerelease_tag_v2.clone();
-    println!(
-        "cargo:rustc-env=GITVERSION_NUGET_PRERELEASE_TAG_V2={}",
-        nuget_prerelease_tag_v2.clone()
-    );
+    let has_nuget_prerelease_tag_v2 = gv.nuget_prerelease_tag_v2.is_some();
+    let nuget_prerelease_tag_v2 = gv.nuget_prerelease_tag_v2.clone().unwrap_or_default();
 
-    let nuget_prerelease_tag = gv.nuget_prerelease_tag.clone();
-    println!(
-        "cargo:rustc-env=GITVERSION_NUGET_PRERELEASE_TAG={}",
-        nuget_prerelease_tag.clone()
-    );
+    let has_nuget_prerelease_tag = gv.nuget_prerelease_tag.is_some();
+    let nuget_prerelease_tag = gv.nuget_prerelease_tag.clone().unwrap_or_default();
 
     let version_source_sha = gv.version_source_sha.clone();
-    println!(
-        "cargo:rustc-env=GITVERSION_VERSION_SOURCE_SHA={}",
-        version_source_sha.clone()
-    );
 
     let commits_since_version_source = gv.commits_since_version_source;
-    println!(
-        "cargo:rustc-env=GITVERSION_COMMITS_SINCE_VERSION_SOURCE={}",
-        commits_since_version_source.clone()
-    );
 
-    let commits_since_version_source_padded = gv.commits_since_version_source_padded.clone();
-    println!(
-        "cargo:rustc-env=GITVERSION_COMMITS_SINCE_VERSION_SOURCE_PADDED={}",
-        commits_since_version_source_padded.clone()
-    );
+    let has_commits_since_version_source_padded = gv.commits_since_version_source_padded.is_some();
+    let commits_since_version_source_padded = gv
+        .commits_since_version_source_padded
+        .clone()
+        .unwrap_or_default();
 
     let uncommitted_changes = gv.uncommitted_changes;
-    println!(
-        "cargo:rustc-env=GITVERSION_UNCOMMITTED_CHANGES={}",
-        uncommitted_changes.to_string()
-    );
 
     let commit_date = gv.commit_date.clone();
-    println!(
-        "cargo:rustc-env=GITVERSION_COMMIT_DATE={}",
-        commit_date.clone()
-    );
+
+    let is_fallback = gv.is_fallback;
+
+    let has_tool_version = gv.tool_version.is_some();
+    let tool_version = gv.tool_version.clone().unwrap_or_default();
+
+    let schema_version = gv.schema().major();
 
     let tokens = quote! {
         #[allow(dead_code)]
@@ -274,7 +602,7 @@ fn write_version_file(path: &Path) -> Result<GitVersion> {
             /// The build metadata, usually representing number of commits since the `version_source_sha`.
             pub build_meta_data: Option<u32>,
             /// The `build_meta_data` padded with `0` up to `4` digits.
-            pub build_meta_data_padded: &'static str,
+            pub build_meta_data_padded: Option<&'static str>,
             /// The `build_meta_data` suffixed with `branch_name` and `sha`.
             pub full_build_meta_data: &'static str,
             /// `major`, `minor` and `patch` joined together, separated by `.`.
@@ -283,10 +611,10 @@ fn write_version_file(path: &Path) -> Result<GitVersion> {
             pub semver: &'static str,
             /// Equal to `semver`, but without a `.` separating `pre_release_label` and `pre_release_number`.
             #[deprecated]
-            pub legacy_semver: &'static str,
+            pub legacy_semver: Option<&'static str>,
             /// Equal to `legacy_semver`, but with `pre_release_number` padded with `0` up to `4` digits.
             #[deprecated]
-            pub legacy_semver_padded: &'static str,
+            pub legacy_semver_padded: Option<&'static str>,
             /// Defaults to `major.minor.0.0` to allow the assembly to be hotfixed without breaking
             /// existing applications that may be referencing it.
             /// (Suitable for .NET `AssemblyVersion`.)
@@ -311,26 +639,32 @@ fn write_version_file(path: &Path) -> Result<GitVersion> {
             pub short_sha: &'static str,
             /// A NuGet 2.0 compatible version number.
             #[deprecated]
-            pub nuget_version_v2: &'static str,
+            pub nuget_version_v2: Option<&'static str>,
             /// A NuGet 1.0 compatible version number.
             #[deprecated]
-            pub nuget_version: &'static str,
+            pub nuget_version: Option<&'static str>,
             /// A NuGet 2.0 compatible `pre_release_tag`.
             #[deprecated]
-            pub nuget_prerelease_tag_v2: &'static str,
+            pub nuget_prerelease_tag_v2: Option<&'static str>,
             /// A NuGet 1.0 compatible `pre_release_tag`.
             #[deprecated]
-            pub nuget_prerelease_tag: &'static str,
+            pub nuget_prerelease_tag: Option<&'static str>,
             /// The SHA of the commit used as version source.
             pub version_source_sha: &'static str,
             /// The number of commits since the version source.
             pub commits_since_version_source: u32,
             /// The `commits_since_version_source` padded with `0` up to `4` digits.
-            pub commits_since_version_source_padded: &'static str,
+            pub commits_since_version_source_padded: Option<&'static str>,
             /// The ISO-8601 formatted date of the commit identified by `sha`.
             pub uncommitted_changes: u32,
             /// The number of uncommitted changes present in the repository.
             pub commit_date: &'static str,
+            /// The version of GitVersion.Tool that calculated the version, if it was run.
+            pub tool_version: Option<&'static str>,
+            /// Whether the version was obtained from a fallback source instead of GitVersion.
+            pub is_fallback: bool,
+            /// The major version of the GitVersion JSON schema the values were read from.
+            pub schema_version: u32,
         }
 
         #[allow(dead_code)]
@@ -349,12 +683,12 @@ fn write_version_file(path: &Path) -> Result<GitVersion> {
                     pre_release_number: if #has_pre_release_number { Some( #pre_release_number ) } else { None },
                     weighted_pre_release_number: #weighted_pre_release_number,
                     build_meta_data: if #has_build_meta_data { Some( #build_meta_data ) } else { None },
-                    build_meta_data_padded: #build_meta_data_padded,
+                    build_meta_data_padded: if #has_build_meta_data_padded { Some( #build_meta_data_padded ) } else { None },
                     full_build_meta_data: #full_build_meta_data,
                     major_minor_patch: #major_minor_patch,
                     semver: #semver,
-                    legacy_semver: #legacy_semver,
-                    legacy_semver_padded: #legacy_semver_padded,
+                    legacy_semver: if #has_legacy_semver { Some( #legacy_semver ) } else { None },
+                    legacy_semver_padded: if #has_legacy_semver_padded { Some( #legacy_semver_padded ) } else { None },
                     assembly_semver: #assembly_semver,
                     assembly_sem_file_version: #assembly_sem_file_version,
                     informational_version: #informational_version,
@@ -363,15 +697,18 @@ fn write_version_file(path: &Path) -> Result<GitVersion> {
                     escaped_branch_name: #escaped_branch_name,
                     sha: #sha,
                     short_sha: #short_sha,
-                    nuget_version_v2: #nuget_version_v2,
-                    nuget_version: #nuget_version,
-                    nuget_prerelease_tag_v2: #nuget_prerelease_tag_v2,
-                    nuget_prerelease_tag: #nuget_prerelease_tag,
+                    nuget_version_v2: if #has_nuget_version_v2 { Some( #nuget_version_v2 ) } else { None },
+                    nuget_version: if #has_nuget_version { Some( #nuget_version ) } else { None },
+                    nuget_prerelease_tag_v2: if #has_nuget_prerelease_tag_v2 { Some( #nuget_prerelease_tag_v2 ) } else { None },
+                    nuget_prerelease_tag: if #has_nuget_prerelease_tag { Some( #nuget_prerelease_tag ) } else { None },
                     version_source_sha: #version_source_sha,
                     commits_since_version_source: #commits_since_version_source,
-                    commits_since_version_source_padded: #commits_since_version_source_padded,
+                    commits_since_version_source_padded: if #has_commits_since_version_source_padded { Some( #commits_since_version_source_padded ) } else { None },
                     uncommitted_changes: #uncommitted_changes,
-                    commit_date: #commit_date
+                    commit_date: #commit_date,
+                    tool_version: if #has_tool_version { Some( #tool_version ) } else { None },
+                    is_fallback: #is_fallback,
+                    schema_version: #schema_version
                 }
             }
         }
@@ -401,33 +738,201 @@ fn write_version_file(path: &Path) -> Result<GitVersion> {
 
     let code = tokens.to_string();
     let is_fresh = if path.exists() {
-        same_content_as(&path, &code)?
+        same_content_as(path, &code)?
     } else {
         false
     };
 
     if !is_fresh {
-        let mut file = BufWriter::new(File::create(&path)?);
+        let mut file = BufWriter::new(File::create(path)?);
         write!(file, "{}", code)?;
     }
-    Ok(gv)
+    Ok(())
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use emit::Discard;
     use tempfile::NamedTempFile;
 
+    pub(crate) const GITVERSION_6_JSON: &str = r#"{
+        "AssemblySemFileVer": "1.2.3.0",
+        "AssemblySemVer": "1.2.3.0",
+        "BranchName": "feature/x",
+        "BuildMetaData": null,
+        "CommitDate": "2024-03-01",
+        "CommitsSinceVersionSource": 4,
+        "EscapedBranchName": "feature-x",
+        "FullBuildMetaData": "Branch.feature-x.Sha.abcdef0",
+        "FullSemVer": "1.2.3-x.4",
+        "InformationalVersion": "1.2.3-x.4+Branch.feature-x.Sha.abcdef0",
+        "Major": 1,
+        "MajorMinorPatch": "1.2.3",
+        "Minor": 2,
+        "Patch": 3,
+        "PreReleaseLabel": "x",
+        "PreReleaseLabelWithDash": "-x",
+        "PreReleaseNumber": 4,
+        "PreReleaseTag": "x.4",
+        "PreReleaseTagWithDash": "-x.4",
+        "SemVer": "1.2.3-x.4",
+        "Sha": "abcdef0",
+        "ShortSha": "abcdef0",
+        "UncommittedChanges": 0,
+        "VersionSourceSha": "1234567",
+        "WeightedPreReleaseNumber": 30004
+    }"#;
+
     #[test]
     pub fn json_is_created() {
-        let result = dotnet_gitversion().expect("dotnet_gitversion");
+        let result =
+            dotnet_gitversion(&GitVersionBuild::new(), &mut Discard).expect("dotnet_gitversion");
         assert!(result.len() > 8);
     }
 
+    #[test]
+    pub fn invalid_json_names_field() {
+        match GitVersion::from_json("{}") {
+            Err(Error::InvalidJson { field, .. }) => assert_eq!(field.as_deref(), Some("Major")),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        match GitVersion::from_json(r#"{"Major": "one"}"#) {
+            Err(Error::InvalidJson { field, .. }) => assert_eq!(field.as_deref(), Some("Major")),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    pub fn parses_gitversion_6_json() -> Result<()> {
+        let gv = GitVersion::from_json(GITVERSION_6_JSON)?;
+        assert_eq!(gv.schema(), SchemaVersion::V6);
+        assert_eq!(gv.semver, "1.2.3-x.4");
+        assert_eq!(gv.build_meta_data, None);
+        assert_eq!(gv.legacy_semver, None);
+        assert_eq!(gv.commits_since_version_source_padded, None);
+        Ok(())
+    }
+
+    #[test]
+    pub fn json_round_trips() -> Result<()> {
+        let gv = GitVersion::from_reader(GITVERSION_6_JSON.as_bytes())?;
+        let json = serde_json::to_string(&gv).expect("serialized");
+        assert!(!json.contains("LegacySemVer"));
+
+        let mut parsed = GitVersion::from_json(&json)?;
+        assert_eq!(parsed, gv);
+        assert_eq!(parsed.schema(), SchemaVersion::V6);
+
+        // Only the variables are compared, not where they came from.
+        parsed.source = VersionSource::Cache;
+        parsed.is_fallback = true;
+        assert_eq!(parsed, gv);
+        parsed.uncommitted_changes += 1;
+        assert_ne!(parsed, gv);
+        Ok(())
+    }
+
+    #[test]
+    pub fn precomputed_json_is_used() -> Result<()> {
+        let file = NamedTempFile::new()?;
+        fs::write(file.path(), GITVERSION_6_JSON)?;
+        let config = GitVersionBuild::new();
+        let mut lines = Vec::new();
+
+        let json = precomputed_json(
+            &config,
+            |name| match name {
+                JSON_FILE_ENV_VAR => Some(file.path().into()),
+                _ => None,
+            },
+            &mut lines,
+        )?;
+        assert_eq!(json.as_deref(), Some(GITVERSION_6_JSON));
+        assert_eq!(
+            lines,
+            vec![format!("cargo:rerun-if-changed={}", file.path().display())]
+        );
+
+        let json = precomputed_json(
+            &config,
+            |name| match name {
+                JSON_ENV_VAR => Some("{}".into()),
+                _ => Some(file.path().into()),
+            },
+            &mut Discard,
+        )?;
+        assert_eq!(json.as_deref(), Some("{}"));
+
+        let json = precomputed_json(&config, |_| Some("".into()), &mut Discard)?;
+        assert_eq!(json, None);
+        Ok(())
+    }
+
+    #[test]
+    pub fn tool_version_is_added_to_json() -> Result<()> {
+        let json = with_tool_version(GITVERSION_6_JSON.into(), Some("6.0.2+Sha.1234".into()));
+        let gv = GitVersion::from_json(&json)?;
+        assert_eq!(gv.tool_version.as_deref(), Some("6.0.2+Sha.1234"));
+        assert_eq!(gv.full_semver, "1.2.3-x.4");
+
+        assert_eq!(
+            with_tool_version("not json".into(), Some("6.0.2".into())),
+            "not json"
+        );
+        Ok(())
+    }
+
     #[test]
     pub fn write_file() -> Result<()> {
-        let mut file = NamedTempFile::new()?;
-        write_version_file(file.path());
+        let file = NamedTempFile::new()?;
+        let gv = GitVersion::from_json(GITVERSION_6_JSON)?;
+        write_version_file(file.path(), &gv)?;
+        let code = fs::read_to_string(file.path())?;
+        assert!(code.contains("pub const GIT_VERSION"));
+        assert!(code.contains("\"1.2.3-x.4\""));
+        Ok(())
+    }
+
+    #[test]
+    pub fn variables_are_emitted_to_sink() -> Result<()> {
+        let gv = GitVersion::from_json(GITVERSION_6_JSON)?;
+        let mut lines = Vec::new();
+        GitVersionBuild::new()
+            .env_prefix("MY_")
+            .emit(&gv, &mut lines);
+        assert!(lines.contains(&"cargo:rustc-env=MY_FULL_SEMVER=1.2.3-x.4".to_string()));
+        assert!(!lines.iter().any(|line| line.contains("LEGACY_SEMVER")));
+        Ok(())
+    }
+
+    #[test]
+    pub fn cfgs_are_emitted_to_sink() -> Result<()> {
+        let mut gv = GitVersion::from_json(GITVERSION_6_JSON)?;
+        let mut lines = Vec::new();
+        GitVersionBuild::new().emit(&gv, &mut lines);
+        let cfgs: Vec<&str> = lines
+            .iter()
+            .filter_map(|line| line.strip_prefix("cargo:rustc-cfg="))
+            .collect();
+        assert_eq!(
+            cfgs,
+            vec![
+                "gitversion_prerelease",
+                r#"gitversion_branch="feature/x""#,
+                r#"gitversion_label="x""#
+            ]
+        );
+        assert!(lines.contains(&"cargo:rustc-check-cfg=cfg(gitversion_dirty)".to_string()));
+
+        gv.uncommitted_changes = 1;
+        let mut lines = Vec::new();
+        GitVersionBuild::new()
+            .rustc_cfgs(false)
+            .emit(&gv, &mut lines);
+        assert!(!lines.iter().any(|line| line.contains("cfg")));
         Ok(())
     }
 }
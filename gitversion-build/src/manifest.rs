@@ -0,0 +1,563 @@
+//! Synchronizing the versions in `Cargo.toml` and `Cargo.lock` with GitVersion.
+//!
+//! Requires the `manifest` feature. Formatting and comments of the rewritten
+//! files are preserved.
+
+use crate::{Error, Result};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml_edit::{DocumentMut, Item, TableLike, Value};
+
+/// The dependency tables whose path dependencies on workspace members are updated.
+const DEPENDENCY_TABLES: &[&str] = &["dependencies", "dev-dependencies", "build-dependencies"];
+
+/// Whether [`sync_versions`] rewrites the files or only checks them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SyncMode {
+    /// Rewrites the files not at the version. This is the default.
+    #[default]
+    Write,
+    /// Fails with [`Error::ManifestOutOfDate`] if any file is not at the version.
+    Check,
+}
+
+/// Sets the versions of the workspace or package defined by `manifest` to `version`,
+/// e.g. the [`semver`](crate::GitVersion::semver) calculated by GitVersion.
+/// For a workspace member, the whole workspace is synchronized, like Cargo
+/// finding the workspace root through `package.workspace` or the parent directories.
+///
+/// This updates
+/// - `[package] version` of the root package and all workspace members, unless inherited,
+/// - `[workspace.package] version`,
+/// - the version requirements of path dependencies on workspace members, including
+///   `[workspace.dependencies]` and target-specific dependencies, keeping operators like `=`,
+/// - the workspace members listed in the `Cargo.lock` of the workspace root.
+///
+/// Workspace `members` may contain `*` and `?` wildcards; other glob syntax fails
+/// with [`Error::InvalidManifest`].
+///
+/// Returns the files that were rewritten, or with [`SyncMode::Check`], nothing.
+pub fn sync_versions(manifest: &Path, version: &str, mode: SyncMode) -> Result<Vec<PathBuf>> {
+    semver::Version::parse(version).map_err(|source| Error::InvalidVersion {
+        version: version.to_owned(),
+        source,
+    })?;
+
+    let root = workspace_root(Manifest::read(manifest)?)?;
+    let mut manifests = vec![root];
+    for dir in members(&manifests[0])? {
+        let path = dir.join("Cargo.toml");
+        if !manifests.iter().any(|m| m.dir == dir) {
+            manifests.push(Manifest::read(&path)?);
+        }
+    }
+
+    let members: Vec<(String, PathBuf)> = manifests
+        .iter()
+        .filter_map(|m| Some((m.package_name()?, m.dir.clone())))
+        .collect();
+
+    let mut changed = Vec::new();
+    for manifest in &mut manifests {
+        if manifest.sync(version, &members) {
+            changed.push(manifest.path.clone());
+        }
+    }
+
+    let lock_path = manifests[0].dir.join("Cargo.lock");
+    let mut lock = match fs::read_to_string(&lock_path) {
+        Ok(content) => Some(parse(&lock_path, &content)?),
+        Err(_) => None,
+    };
+    let lock_changed = match &mut lock {
+        Some(lock) => sync_lock(lock, version, &members),
+        None => false,
+    };
+    if lock_changed {
+        changed.push(lock_path.clone());
+    }
+
+    if mode == SyncMode::Check {
+        return match changed.is_empty() {
+            true => Ok(Vec::new()),
+            false => Err(Error::ManifestOutOfDate {
+                version: version.to_owned(),
+                paths: changed,
+            }),
+        };
+    }
+
+    for manifest in &manifests {
+        if changed.contains(&manifest.path) {
+            fs::write(&manifest.path, manifest.doc.to_string())?;
+        }
+    }
+    if let (true, Some(lock)) = (lock_changed, &lock) {
+        fs::write(&lock_path, lock.to_string())?;
+    }
+    Ok(changed)
+}
+
+/// A parsed `Cargo.toml`.
+struct Manifest {
+    path: PathBuf,
+    dir: PathBuf,
+    doc: DocumentMut,
+}
+
+impl Manifest {
+    fn read(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        let dir = path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .map_or_else(|| PathBuf::from("."), Path::to_path_buf);
+        Ok(Self {
+            path: path.to_path_buf(),
+            dir: canonical(&dir),
+            doc: parse(path, &content)?,
+        })
+    }
+
+    fn package_name(&self) -> Option<String> {
+        self.doc
+            .get("package")?
+            .get("name")?
+            .as_str()
+            .map(str::to_owned)
+    }
+
+    /// Updates all versions, returning whether anything changed.
+    fn sync(&mut self, version: &str, members: &[(String, PathBuf)]) -> bool {
+        let mut changed = false;
+        for table in &["package", "workspace.package"] {
+            // `Item::get_mut` inserts missing keys, hence the lookups through `TableLike`.
+            let mut item = Some(self.doc.as_item_mut());
+            for key in table.split('.').chain(Some("version")) {
+                item = item
+                    .and_then(Item::as_table_like_mut)
+                    .and_then(|table| table.get_mut(key));
+            }
+            if let Some(item) = item {
+                changed |= set_version(item, version, false);
+            }
+        }
+
+        let dir = self.dir.clone();
+        let mut tables: Vec<&mut dyn TableLike> = Vec::new();
+        let root = self.doc.as_table_mut();
+        let (workspace, rest): (Vec<_>, Vec<_>) = root
+            .iter_mut()
+            .partition(|(key, _)| key.get() == "workspace");
+        for (key, item) in rest {
+            if key.get() == "target" {
+                if let Some(targets) = item.as_table_like_mut() {
+                    for (_, target) in targets.iter_mut() {
+                        if let Some(target) = target.as_table_like_mut() {
+                            collect_dependency_tables(target, &mut tables);
+                        }
+                    }
+                }
+            } else if DEPENDENCY_TABLES.contains(&key.get()) {
+                if let Some(deps) = item.as_table_like_mut() {
+                    tables.push(deps);
+                }
+            }
+        }
+        for (_, item) in workspace {
+            if let Some(deps) = item
+                .as_table_like_mut()
+                .and_then(|workspace| workspace.get_mut("dependencies"))
+                .and_then(Item::as_table_like_mut)
+            {
+                tables.push(deps);
+            }
+        }
+
+        for deps in tables {
+            for (_, dep) in deps.iter_mut() {
+                changed |= sync_dependency(dep, &dir, version, members);
+            }
+        }
+        changed
+    }
+}
+
+fn collect_dependency_tables<'a>(
+    table: &'a mut dyn TableLike,
+    out: &mut Vec<&'a mut dyn TableLike>,
+) {
+    for (key, item) in table.iter_mut() {
+        if DEPENDENCY_TABLES.contains(&key.get()) {
+            if let Some(deps) = item.as_table_like_mut() {
+                out.push(deps);
+            }
+        }
+    }
+}
+
+/// Updates the version requirement of `dep` if it is a path dependency on a member.
+fn sync_dependency(
+    dep: &mut Item,
+    dir: &Path,
+    version: &str,
+    members: &[(String, PathBuf)],
+) -> bool {
+    let dep = match dep.as_table_like_mut() {
+        Some(dep) => dep,
+        None => return false,
+    };
+    let path = match dep.get("path").and_then(Item::as_str) {
+        Some(path) => canonical(&dir.join(path)),
+        None => return false,
+    };
+    if !members.iter().any(|(_, member)| *member == path) {
+        return false;
+    }
+    match dep.get_mut("version") {
+        Some(item) => set_version(item, version, true),
+        None => false,
+    }
+}
+
+/// Updates the `[[package]]` entries of workspace members in a `Cargo.lock`.
+fn sync_lock(lock: &mut DocumentMut, version: &str, members: &[(String, PathBuf)]) -> bool {
+    let packages = match lock
+        .get_mut("package")
+        .and_then(Item::as_array_of_tables_mut)
+    {
+        Some(packages) => packages,
+        None => return false,
+    };
+    let names: BTreeSet<&str> = members.iter().map(|(name, _)| name.as_str()).collect();
+    let mut changed = false;
+    for package in packages.iter_mut() {
+        let is_member = matches!(package.get("name").and_then(Item::as_str), Some(name) if names.contains(name));
+        // Registry and Git packages have a `source`, members do not.
+        if is_member && !package.contains_key("source") {
+            if let Some(item) = package.get_mut("version") {
+                changed |= set_version(item, version, false);
+            }
+        }
+    }
+    changed
+}
+
+/// Sets the string `item` to `version`, keeping its comments. With `requirement`,
+/// a leading operator such as `=` or `^` is kept. Non-strings, e.g. inherited
+/// `version.workspace = true`, are left alone.
+fn set_version(item: &mut Item, version: &str, requirement: bool) -> bool {
+    let value = match item.as_value_mut() {
+        Some(value) => value,
+        None => return false,
+    };
+    let current = match value.as_str() {
+        Some(current) => current,
+        None => return false,
+    };
+    let operator = if requirement {
+        let end = current
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(current.len());
+        &current[..end]
+    } else {
+        ""
+    };
+    let updated = format!("{}{}", operator, version);
+    if current == updated {
+        return false;
+    }
+    let decor = value.decor().clone();
+    *value = Value::from(updated);
+    *value.decor_mut() = decor;
+    true
+}
+
+/// The root of the workspace `manifest` belongs to: the manifest named by
+/// `package.workspace`, or the closest manifest in the parent directories with a
+/// `[workspace]` listing it as a member. Returns `manifest` itself if it is the root
+/// or not part of a workspace.
+fn workspace_root(manifest: Manifest) -> Result<Manifest> {
+    if manifest.doc.contains_key("workspace") {
+        return Ok(manifest);
+    }
+    let explicit = manifest
+        .doc
+        .get("package")
+        .and_then(|package| package.get("workspace"))
+        .and_then(Item::as_str);
+    if let Some(dir) = explicit {
+        return Manifest::read(&manifest.dir.join(dir).join("Cargo.toml"));
+    }
+
+    for dir in manifest.dir.ancestors().skip(1) {
+        let path = dir.join("Cargo.toml");
+        if !path.is_file() {
+            continue;
+        }
+        let candidate = Manifest::read(&path)?;
+        if candidate.doc.contains_key("workspace") {
+            return match members(&candidate)?.contains(&manifest.dir) {
+                true => Ok(candidate),
+                false => Ok(manifest),
+            };
+        }
+    }
+    Ok(manifest)
+}
+
+/// The member directories listed in `[workspace] members`, expanding `*` and `?`
+/// wildcards and skipping `exclude`d directories.
+fn members(root: &Manifest) -> Result<Vec<PathBuf>> {
+    let workspace = match root.doc.get("workspace") {
+        Some(workspace) => workspace,
+        None => return Ok(Vec::new()),
+    };
+    let strings = |key: &str| -> Vec<String> {
+        workspace
+            .get(key)
+            .and_then(Item::as_array)
+            .map(|array| {
+                array
+                    .iter()
+                    .filter_map(|v| v.as_str())
+                    .map(str::to_owned)
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let excluded: Vec<PathBuf> = strings("exclude")
+        .iter()
+        .map(|dir| canonical(&root.dir.join(dir)))
+        .collect();
+
+    let mut dirs = Vec::new();
+    for member in strings("members") {
+        if member.contains(&['[', ']', '{', '}'][..]) || member.contains("**") {
+            return Err(Error::InvalidManifest {
+                path: root.path.clone(),
+                message: format!(
+                    "unsupported glob {:?} in workspace members; only `*` and `?` are supported",
+                    member
+                ),
+            });
+        }
+        if !member.contains(&WILDCARDS[..]) {
+            dirs.push(root.dir.join(member));
+            continue;
+        }
+
+        // Expand the pattern one path component at a time.
+        let mut matches = vec![root.dir.clone()];
+        for component in Path::new(&member).components() {
+            let pattern = component.as_os_str().to_string_lossy();
+            let mut next = Vec::new();
+            for dir in &matches {
+                if !pattern.contains(&WILDCARDS[..]) {
+                    next.push(dir.join(&*pattern));
+                    continue;
+                }
+                let mut entries: Vec<PathBuf> = match fs::read_dir(dir) {
+                    Ok(entries) => entries
+                        .filter_map(|e| e.ok())
+                        .filter(|e| wildcard_match(&pattern, &e.file_name().to_string_lossy()))
+                        .map(|e| e.path())
+                        .filter(|path| path.is_dir())
+                        .collect(),
+                    Err(_) => continue,
+                };
+                entries.sort();
+                next.extend(entries);
+            }
+            matches = next;
+        }
+        dirs.extend(
+            matches
+                .into_iter()
+                .filter(|dir| dir.join("Cargo.toml").is_file()),
+        );
+    }
+    Ok(dirs
+        .iter()
+        .map(|dir| canonical(dir))
+        .filter(|dir| !excluded.contains(dir))
+        .collect())
+}
+
+/// The wildcards supported in workspace `members`.
+const WILDCARDS: [char; 2] = ['*', '?'];
+
+/// Matches `name` against `pattern`, in which `*` matches any sequence of
+/// characters and `?` any single character.
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    // `star` is the position after the last `*` and the name position it matched up to.
+    let (mut p, mut n, mut star) = (0, 0, None);
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+fn parse(path: &Path, content: &str) -> Result<DocumentMut> {
+    content
+        .parse::<DocumentMut>()
+        .map_err(|e| Error::InvalidManifest {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        })
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::TempDir;
+
+    const ROOT: &str = r#"[workspace]
+members = ["crates/*"]
+
+[workspace.package]
+version = "0.1.0" # updated by cargo gitversion
+
+[workspace.dependencies]
+a = { path = "crates/a", version = "=0.1.0" }
+serde = "1.0"
+"#;
+
+    const A: &str = r#"[package]
+name = "a"
+version.workspace = true
+"#;
+
+    const B: &str = r#"[package]
+name = "b"
+version = "0.1.0"
+
+[dependencies]
+a = { path = "../a", version = "0.1.0" }
+serde = { version = "1.0" }
+
+[target.'cfg(unix)'.dev-dependencies.a]
+path = "../a"
+version = "^0.1"
+"#;
+
+    const LOCK: &str = r#"version = 3
+
+[[package]]
+name = "a"
+version = "0.1.0"
+
+[[package]]
+name = "b"
+version = "0.1.0"
+
+[[package]]
+name = "serde"
+version = "1.0.0"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+"#;
+
+    #[test]
+    pub fn rewrites_workspace_versions() -> Result<()> {
+        let root = TempDir::new()?;
+        fs::create_dir_all(root.path().join("crates/a"))?;
+        fs::create_dir_all(root.path().join("crates/b"))?;
+        fs::write(root.path().join("Cargo.toml"), ROOT)?;
+        fs::write(root.path().join("crates/a/Cargo.toml"), A)?;
+        fs::write(root.path().join("crates/b/Cargo.toml"), B)?;
+        fs::write(root.path().join("Cargo.lock"), LOCK)?;
+        let manifest = root.path().join("Cargo.toml");
+
+        match sync_versions(&manifest, "0.2.0-beta.1", SyncMode::Check) {
+            Err(Error::ManifestOutOfDate { paths, .. }) => assert_eq!(paths.len(), 3),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let changed = sync_versions(&manifest, "0.2.0-beta.1", SyncMode::Write)?;
+        assert_eq!(changed.len(), 3);
+        assert_eq!(
+            fs::read_to_string(&manifest)?,
+            ROOT.replace("\"0.1.0\"", "\"0.2.0-beta.1\"")
+                .replace("\"=0.1.0\"", "\"=0.2.0-beta.1\"")
+        );
+        assert_eq!(
+            fs::read_to_string(root.path().join("crates/a/Cargo.toml"))?,
+            A
+        );
+        assert_eq!(
+            fs::read_to_string(root.path().join("crates/b/Cargo.toml"))?,
+            B.replace("\"0.1.0\"", "\"0.2.0-beta.1\"")
+                .replace("\"^0.1\"", "\"^0.2.0-beta.1\"")
+        );
+        assert_eq!(
+            fs::read_to_string(root.path().join("Cargo.lock"))?,
+            LOCK.replace("\"0.1.0\"", "\"0.2.0-beta.1\"")
+        );
+
+        assert!(sync_versions(&manifest, "0.2.0-beta.1", SyncMode::Check)?.is_empty());
+        assert!(sync_versions(&manifest, "not a version", SyncMode::Check).is_err());
+
+        // Run from a member, the whole workspace is synchronized.
+        let member = root.path().join("crates/b/Cargo.toml");
+        let changed = sync_versions(&member, "0.3.0", SyncMode::Write)?;
+        assert_eq!(changed.len(), 3);
+        assert!(fs::read_to_string(root.path().join("Cargo.lock"))?.contains("\"0.3.0\""));
+        Ok(())
+    }
+
+    #[test]
+    pub fn expands_member_wildcards() -> Result<()> {
+        let root = TempDir::new()?;
+        fs::create_dir_all(root.path().join("crates/a"))?;
+        fs::create_dir_all(root.path().join("crates/b"))?;
+        fs::create_dir_all(root.path().join("crates/docs"))?;
+        fs::write(root.path().join("crates/a/Cargo.toml"), A)?;
+        fs::write(root.path().join("crates/b/Cargo.toml"), B)?;
+        fs::write(root.path().join("crates/docs/Cargo.toml"), B)?;
+        let manifest = root.path().join("Cargo.toml");
+
+        fs::write(&manifest, "[workspace]\nmembers = [\"c*s/?\"]\n")?;
+        let root_manifest = Manifest::read(&manifest)?;
+        let names: Vec<_> = members(&root_manifest)?
+            .iter()
+            .map(|dir| dir.file_name().expect("name").to_owned())
+            .collect();
+        assert_eq!(names, vec!["a", "b"]);
+
+        fs::write(&manifest, "[workspace]\nmembers = [\"crates/[ab]\"]\n")?;
+        match sync_versions(&manifest, "0.2.0", SyncMode::Check) {
+            Err(e @ Error::InvalidManifest { .. }) => {
+                assert!(e.to_string().contains("unsupported glob \"crates/[ab]\""))
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        assert!(wildcard_match("*-cli", "gitversion-cli"));
+        assert!(!wildcard_match("a?", "a"));
+        Ok(())
+    }
+}
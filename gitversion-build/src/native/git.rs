@@ -0,0 +1,167 @@
+use crate::{Error, Result};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A thin wrapper around the `git` command line.
+///
+/// `HEAD` refers to the inspected commit throughout, which may differ from the checked out one.
+pub(crate) struct Git {
+    dir: PathBuf,
+    head: String,
+}
+
+/// A tag reachable from `HEAD`.
+pub(crate) struct Tag {
+    pub name: String,
+    pub commit: String,
+}
+
+impl Git {
+    /// Inspects the repository in `dir` at `head`, e.g. `HEAD` or a commit SHA.
+    pub fn new(dir: &Path, head: &str) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            head: head.to_owned(),
+        }
+    }
+
+    fn run(&self, args: &[&str]) -> Result<String> {
+        let out = Command::new("git")
+            .args(args)
+            .current_dir(&self.dir)
+            .output()
+            .map_err(|e| match e.kind() {
+                ErrorKind::NotFound => Error::ToolNotFound("git".into()),
+                _ => Error::Io(e),
+            })?;
+
+        if !out.status.success() {
+            return Err(Error::GitFailed {
+                args: args.join(" "),
+                stderr: String::from_utf8_lossy(&out.stderr).trim().to_owned(),
+            });
+        }
+        Ok(String::from_utf8_lossy(&out.stdout).trim().to_owned())
+    }
+
+    fn lines(&self, args: &[&str]) -> Result<Vec<String>> {
+        Ok(self
+            .run(args)?
+            .lines()
+            .filter(|l| !l.is_empty())
+            .map(str::to_owned)
+            .collect())
+    }
+
+    /// The root of the working tree.
+    pub fn toplevel(&self) -> Result<PathBuf> {
+        self.run(&["rev-parse", "--show-toplevel"])
+            .map(PathBuf::from)
+    }
+
+    /// The SHA of `rev`.
+    pub fn rev_parse(&self, rev: &str) -> Result<String> {
+        self.run(&["rev-parse", "--verify", "--quiet", rev])
+    }
+
+    /// The short name of the checked out branch, or `None` for a detached `HEAD`.
+    pub fn current_branch(&self) -> Result<Option<String>> {
+        if self.head != "HEAD" {
+            return Ok(None);
+        }
+        match self.run(&["symbolic-ref", "--short", "-q", "HEAD"]) {
+            Ok(branch) => Ok(Some(branch)),
+            Err(Error::GitFailed { .. }) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The local branches pointing at `HEAD`.
+    pub fn branches_at_head(&self) -> Result<Vec<String>> {
+        self.lines(&[
+            "for-each-ref",
+            &format!("--points-at={}", self.head),
+            "--format=%(refname:strip=2)",
+            "refs/heads",
+        ])
+    }
+
+    /// The tags reachable from `HEAD`, with annotated tags resolved to their commits.
+    pub fn tags_merged_into_head(&self) -> Result<Vec<Tag>> {
+        let lines = self.lines(&[
+            "for-each-ref",
+            &format!("--merged={}", self.head),
+            "--format=%(refname:strip=2)%09%(objectname)%09%(*objectname)",
+            "refs/tags",
+        ])?;
+        Ok(lines
+            .iter()
+            .filter_map(|line| {
+                let mut parts = line.split('\t');
+                let name = parts.next()?.to_owned();
+                let object = parts.next()?;
+                let commit = match parts.next() {
+                    Some(peeled) if !peeled.is_empty() => peeled,
+                    _ => object,
+                };
+                Some(Tag {
+                    name,
+                    commit: commit.to_owned(),
+                })
+            })
+            .collect())
+    }
+
+    /// The number of commits reachable from `HEAD` but not from `since`.
+    pub fn count_commits(&self, since: Option<&str>) -> Result<u32> {
+        let range = match since {
+            Some(since) => format!("{}..{}", since, self.head),
+            None => self.head.clone(),
+        };
+        let count = self.run(&["rev-list", "--count", &range])?;
+        Ok(count.parse().unwrap_or(0))
+    }
+
+    /// The messages of the commits reachable from `HEAD` but not from `since`.
+    pub fn commit_messages(&self, since: Option<&str>) -> Result<Vec<String>> {
+        let range = match since {
+            Some(since) => format!("{}..{}", since, self.head),
+            None => self.head.clone(),
+        };
+        let log = self.run(&["log", "--format=%B%x00", &range])?;
+        Ok(log
+            .split('\0')
+            .map(str::trim)
+            .filter(|m| !m.is_empty())
+            .map(str::to_owned)
+            .collect())
+    }
+
+    /// The oldest root commit reachable from `HEAD`.
+    pub fn root_commit(&self) -> Result<Option<String>> {
+        Ok(self
+            .lines(&["rev-list", "--max-parents=0", &self.head])?
+            .pop())
+    }
+
+    /// The best common ancestor of `HEAD` and `other`, if any.
+    pub fn merge_base(&self, other: &str) -> Result<Option<String>> {
+        match self.run(&["merge-base", &self.head, other]) {
+            Ok(sha) => Ok(Some(sha)),
+            Err(Error::GitFailed { .. }) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The ISO-8601 committer date of `HEAD`.
+    pub fn commit_date(&self) -> Result<String> {
+        let date = self.run(&["log", "-1", "--format=%cI", &self.head])?;
+        Ok(date.chars().take(10).collect())
+    }
+
+    /// The number of changed and untracked files in the working tree.
+    pub fn uncommitted_changes(&self) -> Result<u32> {
+        Ok(self.lines(&["status", "--porcelain"])?.len() as u32)
+    }
+}
@@ -0,0 +1,673 @@
+//! Calculates the GitVersion variables in Rust, using the `git` command line
+//! instead of .NET and `GitVersion.Tool`.
+//!
+//! The calculation follows GitVersion 5 for the `ContinuousDelivery` and
+//! `ContinuousDeployment` modes: base versions are taken from tags reachable
+//! from `HEAD`, `next-version`, release branch names and the `0.1.0` fallback,
+//! incremented per branch configuration or `+semver:` commit messages.
+//! Merge message strategies and the `Mainline` mode are not supported.
+
+mod git;
+
+use crate::builder::Target;
+use crate::config::{BranchConfig, CommitMessageIncrementMode, Config, Increment, VersioningMode};
+use crate::gitversion::{GitVersion, VersionParts, VersionSource};
+use crate::repository::CONFIG_FILE;
+use crate::{Error, Result};
+use git::Git;
+use regex::{Regex, RegexBuilder};
+use std::cmp::Ordering;
+
+/// The branch name GitVersion reports for a detached `HEAD`.
+const NO_BRANCH: &str = "(no branch)";
+
+/// A semantic version as used during the calculation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Version {
+    major: u32,
+    minor: u32,
+    patch: u32,
+    label: String,
+    number: Option<u32>,
+}
+
+impl Version {
+    fn parse(version: &str) -> Option<Self> {
+        let version = version.split('+').next()?;
+        let (core, pre) = match version.find('-') {
+            Some(pos) => (&version[..pos], &version[pos + 1..]),
+            None => (version, ""),
+        };
+
+        // GitVersion accepts `1` and `1.2` as well.
+        let mut numbers = core.split('.');
+        let major = numbers.next()?.parse().ok()?;
+        let minor = numbers.next().map_or(Some(0), |n| n.parse().ok())?;
+        let patch = numbers.next().map_or(Some(0), |n| n.parse().ok())?;
+        if numbers.next().is_some() {
+            return None;
+        }
+
+        let (label, number) = match pre.rfind('.') {
+            Some(pos) if pre[pos + 1..].parse::<u32>().is_ok() => {
+                (pre[..pos].to_owned(), pre[pos + 1..].parse().ok())
+            }
+            _ => (pre.to_owned(), None),
+        };
+        Some(Self {
+            major,
+            minor,
+            patch,
+            label,
+            number,
+        })
+    }
+
+    fn same_core(&self, other: &Version) -> bool {
+        (self.major, self.minor, self.patch) == (other.major, other.minor, other.patch)
+    }
+
+    /// Increments the version; pre-release versions increment their number instead.
+    fn increment(&self, increment: Increment) -> Version {
+        let mut next = self.clone();
+        if !self.label.is_empty() {
+            next.number = self.number.map(|n| n + 1);
+            return next;
+        }
+        match increment {
+            Increment::Major => {
+                next.major += 1;
+                next.minor = 0;
+                next.patch = 0;
+            }
+            Increment::Minor => {
+                next.minor += 1;
+                next.patch = 0;
+            }
+            Increment::Patch | Increment::Inherit => next.patch += 1,
+            Increment::None => {}
+        }
+        next
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.major, self.minor, self.patch)
+            .cmp(&(other.major, other.minor, other.patch))
+            .then_with(|| match (self.label.is_empty(), other.label.is_empty()) {
+                (true, true) => Ordering::Equal,
+                (true, false) => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                (false, false) => self
+                    .label
+                    .cmp(&other.label)
+                    .then_with(|| self.number.cmp(&other.number)),
+            })
+    }
+}
+
+/// A candidate version together with the commit it originates from.
+struct BaseVersion {
+    version: Version,
+    source: Option<String>,
+    should_increment: bool,
+}
+
+/// Calculates the version of the repository containing `dir`.
+pub(crate) fn calculate(target: &Target) -> Result<GitVersion> {
+    let commit = target.commit.as_deref().unwrap_or("HEAD");
+    let git = Git::new(&target.dir, commit);
+    let config_file = match &target.config_file {
+        Some(config_file) => config_file.clone(),
+        None => git.toplevel()?.join(CONFIG_FILE),
+    };
+    let mut config = Config::load(&config_file)?;
+    config.overlay(&target.overrides.to_config()?);
+
+    let head = git.rev_parse(commit)?;
+    let branch = match &target.branch {
+        Some(branch) => Some(branch.clone()),
+        None => git.current_branch()?,
+    };
+    let branches = config.effective_branches();
+
+    // A detached HEAD uses the configuration of a branch pointing at it.
+    let config_branch = match &branch {
+        Some(branch) => Some(branch.clone()),
+        None => git.branches_at_head()?.into_iter().next(),
+    };
+    let (branch_regex, branch_config) = match &config_branch {
+        Some(name) => find_branch_config(&branches, name)?,
+        None => (None, unknown_branch_config()),
+    };
+    let branch_name = branch.unwrap_or_else(|| NO_BRANCH.to_owned());
+
+    let mode = branch_config
+        .mode
+        .or(config.mode)
+        .unwrap_or(VersioningMode::ContinuousDelivery);
+    if mode == VersioningMode::Mainline {
+        return Err(Error::UnsupportedConfig(
+            "the Mainline mode is not supported by the native backend".into(),
+        ));
+    }
+
+    let tag_prefix = compile(&format!(
+        "^(?:{})?",
+        config.tag_prefix.as_deref().unwrap_or("[vV]")
+    ))?;
+    let tags: Vec<(Version, String)> = git
+        .tags_merged_into_head()?
+        .into_iter()
+        .filter_map(|tag| {
+            let prefix = tag_prefix.find(&tag.name)?;
+            let version = Version::parse(&tag.name[prefix.end()..])?;
+            Some((version, tag.commit))
+        })
+        .collect();
+
+    // The current commit is tagged; its version is used as-is.
+    if let Some((version, _)) = tags
+        .iter()
+        .filter(|(_, c)| *c == head)
+        .max_by(|a, b| a.0.cmp(&b.0))
+    {
+        let mut parts = version_parts(version, None, &branch_config, &config);
+        parts.version_source_sha = head.clone();
+        return finish(&git, parts, branch_name, head);
+    }
+
+    let mut candidates: Vec<BaseVersion> = tags
+        .iter()
+        .map(|(version, commit)| BaseVersion {
+            version: version.clone(),
+            source: Some(commit.clone()),
+            should_increment: true,
+        })
+        .collect();
+
+    if let Some(next) = config.next_version.as_deref().and_then(Version::parse) {
+        candidates.push(BaseVersion {
+            version: next,
+            source: None,
+            should_increment: false,
+        });
+    }
+
+    if branch_config.is_release_branch == Some(true) {
+        if let Some(version) = version_in_branch_name(&branch_name, &tag_prefix) {
+            let source = mainline_merge_base(&git, &branches)?;
+            candidates.push(BaseVersion {
+                version,
+                source,
+                should_increment: false,
+            });
+        }
+    }
+
+    candidates.push(BaseVersion {
+        version: Version::parse("0.1.0").expect("valid version"),
+        source: git.root_commit()?,
+        should_increment: false,
+    });
+
+    let default_increment = match branch_config.increment.or(config.increment) {
+        Some(Increment::Inherit) | None => inherited_increment(&branches),
+        Some(increment) => increment,
+    };
+    let message_mode = branch_config
+        .commit_message_incrementing
+        .or(config.commit_message_incrementing)
+        .unwrap_or(CommitMessageIncrementMode::Enabled);
+
+    let initial_release = Version::parse("1.0.0").expect("valid version");
+    let mut best: Option<(Version, &BaseVersion)> = None;
+    for candidate in &candidates {
+        let version = if candidate.should_increment {
+            let increment = match message_mode {
+                CommitMessageIncrementMode::Disabled => default_increment,
+                _ => {
+                    let messages = git.commit_messages(candidate.source.as_deref())?;
+                    match message_increment(&config, &messages)? {
+                        // Like GitVersion 5, cap commit messages at minor before 1.0.0.
+                        Some(Increment::Major) if candidate.version < initial_release => {
+                            max_increment(Increment::Minor, default_increment)
+                        }
+                        Some(increment) => max_increment(increment, default_increment),
+                        None => default_increment,
+                    }
+                }
+            };
+            candidate.version.increment(increment)
+        } else {
+            candidate.version.clone()
+        };
+
+        let is_better = match &best {
+            Some((best_version, _)) => version > *best_version,
+            None => true,
+        };
+        if is_better {
+            best = Some((version, candidate));
+        }
+    }
+    let (mut version, base) = best.expect("fallback version");
+    let commits = git.count_commits(base.source.as_deref())?;
+
+    // Apply the branch specific pre-release label.
+    let label = branch_specific_tag(&branch_config, &branch_name, branch_regex.as_ref());
+    if (version.label.is_empty() && !label.is_empty())
+        || (!version.label.is_empty() && !label.is_empty() && version.label != label)
+    {
+        let number = tags
+            .iter()
+            .filter(|(v, _)| v.label == label && v.same_core(&version))
+            .filter_map(|(v, _)| v.number)
+            .max()
+            .map_or(1, |n| n + 1);
+        version.label = label;
+        version.number = Some(number);
+    }
+
+    let mut build_meta_data = Some(commits);
+    if mode == VersioningMode::ContinuousDeployment {
+        if version.label.is_empty() {
+            version.label = config
+                .continuous_delivery_fallback_tag
+                .clone()
+                .unwrap_or_else(|| "ci".into());
+        }
+        version.number = build_meta_data.take();
+    }
+
+    let mut parts = version_parts(&version, build_meta_data, &branch_config, &config);
+    parts.version_source_sha = base.source.clone().unwrap_or_default();
+    parts.commits_since_version_source = commits;
+    finish(&git, parts, branch_name, head)
+}
+
+fn version_parts(
+    version: &Version,
+    build_meta_data: Option<u32>,
+    branch_config: &BranchConfig,
+    config: &Config,
+) -> VersionParts {
+    let pre_release_weight = if version.label.is_empty() {
+        config.tag_pre_release_weight.unwrap_or(60000)
+    } else {
+        branch_config.pre_release_weight.unwrap_or(0)
+    };
+    VersionParts {
+        major: version.major,
+        minor: version.minor,
+        patch: version.patch,
+        pre_release_label: version.label.clone(),
+        pre_release_number: version.number,
+        pre_release_weight,
+        build_meta_data,
+        ..VersionParts::default()
+    }
+}
+
+fn finish(
+    git: &Git,
+    mut parts: VersionParts,
+    branch_name: String,
+    head: String,
+) -> Result<GitVersion> {
+    parts.branch_name = branch_name;
+    parts.sha = head;
+    parts.commit_date = git.commit_date()?;
+    parts.uncommitted_changes = git.uncommitted_changes()?;
+    let mut gv = GitVersion::from(parts);
+    gv.source = VersionSource::Native;
+    Ok(gv)
+}
+
+fn compile(pattern: &str) -> Result<Regex> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .build()
+        .map_err(|e| Error::UnsupportedConfig(format!("invalid regex {:?}: {}", pattern, e)))
+}
+
+/// Finds the first configured branch whose regex matches `name`.
+fn find_branch_config(
+    branches: &[(String, BranchConfig)],
+    name: &str,
+) -> Result<(Option<Regex>, BranchConfig)> {
+    for (_, config) in branches {
+        if let Some(pattern) = &config.regex {
+            let regex = compile(pattern)?;
+            if regex.is_match(name) {
+                return Ok((Some(regex), config.clone()));
+            }
+        }
+    }
+    Ok((None, unknown_branch_config()))
+}
+
+/// The configuration GitVersion uses for branches not matching any regex.
+fn unknown_branch_config() -> BranchConfig {
+    BranchConfig {
+        tag: Some("{BranchName}".into()),
+        increment: Some(Increment::Inherit),
+        ..BranchConfig::default()
+    }
+}
+
+/// Resolves `Inherit` using the increment of the mainline branch.
+fn inherited_increment(branches: &[(String, BranchConfig)]) -> Increment {
+    branches
+        .iter()
+        .find(|(_, c)| c.is_mainline == Some(true))
+        .and_then(|(_, c)| c.increment)
+        .filter(|i| *i != Increment::Inherit)
+        .unwrap_or(Increment::Patch)
+}
+
+/// The significance of an increment; `Inherit` must be resolved beforehand.
+fn rank(increment: Increment) -> u8 {
+    match increment {
+        Increment::None => 0,
+        Increment::Patch | Increment::Inherit => 1,
+        Increment::Minor => 2,
+        Increment::Major => 3,
+    }
+}
+
+/// The more significant of two increments.
+fn max_increment(a: Increment, b: Increment) -> Increment {
+    if rank(a) >= rank(b) {
+        a
+    } else {
+        b
+    }
+}
+
+/// The merge base of `HEAD` with the first existing mainline branch.
+fn mainline_merge_base(git: &Git, branches: &[(String, BranchConfig)]) -> Result<Option<String>> {
+    for (name, config) in branches {
+        if config.is_mainline != Some(true) {
+            continue;
+        }
+        for candidate in &[name.as_str(), "main", "master"] {
+            if git.rev_parse(&format!("refs/heads/{}", candidate)).is_ok() {
+                return git.merge_base(candidate);
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Determines the highest increment requested by `+semver:` commit messages.
+fn message_increment(config: &Config, messages: &[String]) -> Result<Option<Increment>> {
+    let patterns = [
+        (
+            Increment::Major,
+            config
+                .major_version_bump_message
+                .as_deref()
+                .unwrap_or(r"\+semver:\s?(breaking|major)"),
+        ),
+        (
+            Increment::Minor,
+            config
+                .minor_version_bump_message
+                .as_deref()
+                .unwrap_or(r"\+semver:\s?(feature|minor)"),
+        ),
+        (
+            Increment::Patch,
+            config
+                .patch_version_bump_message
+                .as_deref()
+                .unwrap_or(r"\+semver:\s?(fix|patch)"),
+        ),
+        (
+            Increment::None,
+            config
+                .no_bump_message
+                .as_deref()
+                .unwrap_or(r"\+semver:\s?(none|skip)"),
+        ),
+    ];
+
+    let mut result = None;
+    for (increment, pattern) in &patterns {
+        let regex = compile(pattern)?;
+        if messages.iter().any(|m| regex.is_match(m)) {
+            result = Some(result.map_or(*increment, |r| max_increment(r, *increment)));
+        }
+    }
+    Ok(result)
+}
+
+/// Resolves `useBranchName` and `{BranchName}` in the branch's `tag`.
+fn branch_specific_tag(config: &BranchConfig, branch_name: &str, regex: Option<&Regex>) -> String {
+    let tag = match config.tag.as_deref() {
+        Some("useBranchName") => "{BranchName}",
+        Some(tag) => tag,
+        None => "",
+    };
+    if !tag.contains("{BranchName}") {
+        return tag.to_owned();
+    }
+
+    let name = match regex {
+        Some(regex) => regex.replace(branch_name, "").into_owned(),
+        None => branch_name.to_owned(),
+    };
+    tag.replace("{BranchName}", &name.replace('/', "-"))
+}
+
+/// Finds a version in a release branch name such as `release/1.2.0`.
+fn version_in_branch_name(branch_name: &str, tag_prefix: &Regex) -> Option<Version> {
+    branch_name.split(&['/', '-'][..]).find_map(|part| {
+        let start = tag_prefix.find(part).map_or(0, |m| m.end());
+        Version::parse(&part[start..]).filter(|v| v.label.is_empty())
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::ConfigOverride;
+    use std::fs;
+    use std::path::Path;
+    use std::process::Command;
+    use tempfile::TempDir;
+
+    const CONFIG: &str = "branches:
+  master:
+    regex: (^master$|^origin\\/master$|^main$|^origin\\/main$)
+    mode: ContinuousDelivery
+    tag: ''
+    increment: Patch
+  develop:
+    regex: dev(elop)?(ment)?$
+    mode: ContinuousDeployment
+    tag: unstable
+    increment: Minor
+  feature:
+    regex: features?[/-]
+    mode: ContinuousDeployment
+    tag: useBranchName
+    increment: Minor
+";
+
+    fn git(dir: &Path, args: &[&str]) {
+        let status = Command::new("git")
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .expect("git");
+        assert!(status.status.success(), "git {:?}: {:?}", args, status);
+    }
+
+    fn repository() -> TempDir {
+        let dir = TempDir::new().expect("temp dir");
+        git(dir.path(), &["init", "-q", "-b", "main"]);
+        fs::write(dir.path().join(CONFIG_FILE), CONFIG).expect("config");
+        git(dir.path(), &["add", "."]);
+        git(dir.path(), &["commit", "-q", "-m", "Initial commit"]);
+        dir
+    }
+
+    fn commit(dir: &Path, message: &str) {
+        git(dir, &["commit", "-q", "--allow-empty", "-m", message]);
+    }
+
+    #[test]
+    pub fn tagged_commit_uses_tag() -> Result<()> {
+        let repo = repository();
+        git(repo.path(), &["tag", "v1.2.0"]);
+
+        let gv = calculate(&Target::new(repo.path()))?;
+        assert_eq!(gv.full_semver, "1.2.0");
+        assert_eq!(gv.branch_name, "main");
+        assert_eq!(gv.commits_since_version_source, 0);
+        Ok(())
+    }
+
+    #[test]
+    pub fn continuous_delivery_increments_patch() -> Result<()> {
+        let repo = repository();
+        git(repo.path(), &["tag", "-a", "v1.2.0", "-m", "Release"]);
+        commit(repo.path(), "Fix");
+        commit(repo.path(), "Fix again");
+
+        let gv = calculate(&Target::new(repo.path()))?;
+        assert_eq!(gv.semver, "1.2.1");
+        assert_eq!(gv.full_semver, "1.2.1+2");
+        assert_eq!(gv.build_meta_data, Some(2));
+        assert_eq!(gv.weighted_pre_release_number, 60000);
+        Ok(())
+    }
+
+    #[test]
+    pub fn continuous_deployment_promotes_commit_count() -> Result<()> {
+        let repo = repository();
+        git(repo.path(), &["tag", "v1.2.0"]);
+        git(repo.path(), &["checkout", "-q", "-b", "develop"]);
+        commit(repo.path(), "Feature");
+        commit(repo.path(), "Feature");
+        commit(repo.path(), "Feature");
+
+        let gv = calculate(&Target::new(repo.path()))?;
+        assert_eq!(gv.full_semver, "1.3.0-unstable.3");
+        assert_eq!(gv.build_meta_data, None);
+        assert_eq!(gv.commits_since_version_source, 3);
+
+        git(repo.path(), &["checkout", "-q", "-b", "feature/some-thing"]);
+        let gv = calculate(&Target::new(repo.path()))?;
+        assert_eq!(gv.semver, "1.3.0-some-thing.3");
+        assert_eq!(gv.escaped_branch_name, "feature-some-thing");
+        Ok(())
+    }
+
+    #[test]
+    pub fn commit_message_bumps_major() -> Result<()> {
+        let repo = repository();
+        git(repo.path(), &["tag", "1.2.0"]);
+        commit(repo.path(), "Break things\n\n+semver: breaking");
+
+        let gv = calculate(&Target::new(repo.path()))?;
+        assert_eq!(gv.full_semver, "2.0.0+1");
+        Ok(())
+    }
+
+    #[test]
+    pub fn commit_message_is_capped_at_minor_before_1_0_0() -> Result<()> {
+        let repo = repository();
+        git(repo.path(), &["tag", "0.3.0"]);
+        commit(repo.path(), "Break things\n\n+semver: breaking");
+
+        let gv = calculate(&Target::new(repo.path()))?;
+        assert_eq!(gv.full_semver, "0.4.0+1");
+        Ok(())
+    }
+
+    #[test]
+    pub fn untagged_repository_uses_fallback_version() -> Result<()> {
+        let repo = repository();
+        commit(repo.path(), "Second");
+
+        let gv = calculate(&Target::new(repo.path()))?;
+        assert_eq!(gv.full_semver, "0.1.0+1");
+        assert_eq!(gv.commits_since_version_source, 1);
+        Ok(())
+    }
+
+    #[test]
+    pub fn target_selects_commit_and_branch() -> Result<()> {
+        let repo = repository();
+        git(repo.path(), &["tag", "v1.2.0"]);
+        commit(repo.path(), "Fix");
+        commit(repo.path(), "Fix again");
+
+        let target = Target {
+            branch: Some("develop".into()),
+            commit: Some("HEAD~1".into()),
+            ..Target::new(repo.path())
+        };
+        let gv = calculate(&target)?;
+        assert_eq!(gv.full_semver, "1.3.0-unstable.1");
+        assert_eq!(gv.branch_name, "develop");
+        Ok(())
+    }
+
+    #[test]
+    pub fn overrides_replace_configuration() -> Result<()> {
+        let repo = repository();
+        git(repo.path(), &["tag", "crate-v1.2.0"]);
+        commit(repo.path(), "Feature");
+
+        let target = Target {
+            overrides: ConfigOverride::new().tag_prefix("crate-v"),
+            ..Target::new(repo.path())
+        };
+        let gv = calculate(&target)?;
+        assert_eq!(gv.full_semver, "1.2.1+1");
+        Ok(())
+    }
+
+    #[test]
+    pub fn branch_increment_beats_global_increment() -> Result<()> {
+        let repo = repository();
+        git(repo.path(), &["tag", "1.2.0"]);
+        commit(repo.path(), "Fix");
+
+        // `master` sets `increment: Patch`, which wins over the global `increment`.
+        let target = Target {
+            overrides: ConfigOverride::new().increment(Increment::Minor),
+            ..Target::new(repo.path())
+        };
+        let gv = calculate(&target)?;
+        assert_eq!(gv.full_semver, "1.2.1+1");
+        Ok(())
+    }
+
+    #[test]
+    pub fn branch_increment_override_beats_configuration_file() -> Result<()> {
+        let repo = repository();
+        git(repo.path(), &["tag", "1.2.0"]);
+        commit(repo.path(), "Fix");
+
+        let target = Target {
+            overrides: ConfigOverride::new().branch_increment("master", Increment::Minor),
+            ..Target::new(repo.path())
+        };
+        let gv = calculate(&target)?;
+        assert_eq!(gv.full_semver, "1.3.0+1");
+        Ok(())
+    }
+}
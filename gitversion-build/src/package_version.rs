@@ -0,0 +1,129 @@
+use crate::emit::{self, Sink};
+use crate::{Error, GitVersion, Result};
+use semver::Version;
+use std::fmt::{self, Display, Formatter};
+
+/// The field of the calculated version `CARGO_PKG_VERSION` is compared against.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VersionField {
+    /// `MajorMinorPatch`, e.g. `1.2.3`, ignoring pre-release labels. This is the default.
+    #[default]
+    MajorMinorPatch,
+    /// `SemVer`, e.g. `1.2.3-beta.1`.
+    SemVer,
+}
+
+impl Display for VersionField {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            VersionField::MajorMinorPatch => "MajorMinorPatch",
+            VersionField::SemVer => "SemVer",
+        })
+    }
+}
+
+/// Determines how a `CARGO_PKG_VERSION` differing from the calculated version is reported
+/// by `build()`, e.g. to keep release builds from shipping with a stale manifest version.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PackageVersionCheck {
+    /// The versions are not compared. This is the default.
+    #[default]
+    Ignore,
+    /// Reports a `cargo:warning`.
+    Warn(VersionField),
+    /// Fails the build with [`Error::PackageVersionMismatch`].
+    Deny(VersionField),
+}
+
+impl PackageVersionCheck {
+    /// Compares `package_version` against `gv`. Fallback versions are not compared.
+    pub(crate) fn check(
+        self,
+        package_version: Option<&str>,
+        gv: &GitVersion,
+        sink: &mut dyn Sink,
+    ) -> Result<()> {
+        let field = match self {
+            PackageVersionCheck::Ignore => return Ok(()),
+            PackageVersionCheck::Warn(field) | PackageVersionCheck::Deny(field) => field,
+        };
+        if gv.is_fallback {
+            return Ok(());
+        }
+        let package_version =
+            package_version.ok_or_else(|| Error::MissingEnvVar("CARGO_PKG_VERSION".into()))?;
+        let (expected, matches) = match field {
+            VersionField::MajorMinorPatch => {
+                let core = Version::parse(package_version)
+                    .map(|v| format!("{}.{}.{}", v.major, v.minor, v.patch));
+                let matches = matches!(&core, Ok(core) if *core == gv.major_minor_patch);
+                (&gv.major_minor_patch, matches)
+            }
+            VersionField::SemVer => (&gv.semver, package_version == gv.semver),
+        };
+        if matches {
+            return Ok(());
+        }
+        let error = Error::PackageVersionMismatch {
+            package_version: package_version.to_owned(),
+            field,
+            expected: expected.clone(),
+        };
+        match self {
+            PackageVersionCheck::Warn(_) => {
+                emit::warning(sink, &error);
+                Ok(())
+            }
+            _ => Err(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::GITVERSION_6_JSON;
+
+    #[test]
+    pub fn package_version_is_compared() -> Result<()> {
+        let mut gv = GitVersion::from_json(GITVERSION_6_JSON)?;
+        gv.major_minor_patch = "1.2.3".into();
+        gv.semver = "1.2.3-beta.4".into();
+        let mut lines = Vec::new();
+
+        PackageVersionCheck::Ignore.check(None, &gv, &mut lines)?;
+        PackageVersionCheck::Deny(VersionField::MajorMinorPatch).check(
+            Some("1.2.3"),
+            &gv,
+            &mut lines,
+        )?;
+        match PackageVersionCheck::Deny(VersionField::SemVer).check(Some("1.2.3"), &gv, &mut lines)
+        {
+            Err(e @ Error::PackageVersionMismatch { .. }) => assert_eq!(
+                e.to_string(),
+                "CARGO_PKG_VERSION is 1.2.3, but GitVersion calculated the SemVer 1.2.3-beta.4"
+            ),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        PackageVersionCheck::Deny(VersionField::MajorMinorPatch).check(
+            Some("1.2.3-beta.1"),
+            &gv,
+            &mut lines,
+        )?;
+        assert!(lines.is_empty());
+
+        PackageVersionCheck::Warn(VersionField::MajorMinorPatch).check(
+            Some("1.2.2"),
+            &gv,
+            &mut lines,
+        )?;
+        assert_eq!(
+            lines,
+            vec!["cargo:warning=CARGO_PKG_VERSION is 1.2.2, but GitVersion calculated the MajorMinorPatch 1.2.3"]
+        );
+
+        gv.is_fallback = true;
+        PackageVersionCheck::Deny(VersionField::SemVer).check(Some("0.0.0"), &gv, &mut lines)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,112 @@
+use crate::{Error, Result};
+use std::io::Read;
+use std::process::{Command, Output, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use wait_timeout::ChildExt;
+
+/// Runs `command` like [`Command::output`], but kills it if it does not finish within
+/// `timeout`, returning [`Error::Timeout`] with the stderr captured until then.
+pub(crate) fn output(command: &mut Command, timeout: Option<Duration>) -> Result<Output> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return Ok(command.output()?),
+    };
+
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let stdout = Reader::new(child.stdout.take());
+    let stderr = Reader::new(child.stderr.take());
+
+    let status = match child.wait_timeout(timeout)? {
+        Some(status) => status,
+        None => {
+            let _ = child.kill();
+            let _ = child.wait();
+            // The pipes may be held open by grandchildren, so readers are not joined.
+            let stderr = String::from_utf8_lossy(&stderr.partial()).trim().to_owned();
+            return Err(Error::Timeout { timeout, stderr });
+        }
+    };
+
+    Ok(Output {
+        status,
+        stdout: stdout.finish(),
+        stderr: stderr.finish(),
+    })
+}
+
+/// A pipe read on a separate thread.
+struct Reader {
+    buffer: Arc<Mutex<Vec<u8>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Reader {
+    fn new<R: Read + Send + 'static>(pipe: Option<R>) -> Self {
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let thread = pipe.map(|mut pipe| {
+            let buffer = Arc::clone(&buffer);
+            thread::spawn(move || {
+                let mut chunk = [0; 4096];
+                loop {
+                    match pipe.read(&mut chunk) {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => buffer.lock().unwrap().extend_from_slice(&chunk[..n]),
+                    }
+                }
+            })
+        });
+        Self { buffer, thread }
+    }
+
+    /// The data read so far.
+    fn partial(&self) -> Vec<u8> {
+        self.buffer.lock().unwrap().clone()
+    }
+
+    /// Waits until the pipe is closed and returns all data.
+    fn finish(self) -> Vec<u8> {
+        if let Some(thread) = self.thread {
+            let _ = thread.join();
+        }
+        let data = self.buffer.lock().unwrap();
+        data.clone()
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    pub fn kills_command_on_timeout() {
+        let start = Instant::now();
+        let result = output(
+            Command::new("sh").args(["-c", "echo partial >&2; exec sleep 10"]),
+            Some(Duration::from_millis(500)),
+        );
+        match result {
+            Err(Error::Timeout { stderr, .. }) => assert_eq!(stderr, "partial"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    pub fn captures_output_within_timeout() -> Result<()> {
+        let out = output(
+            Command::new("sh").args(["-c", "echo out; echo err >&2"]),
+            Some(Duration::from_secs(10)),
+        )?;
+        assert!(out.status.success());
+        assert_eq!(out.stdout, b"out\n");
+        assert_eq!(out.stderr, b"err\n");
+        Ok(())
+    }
+}
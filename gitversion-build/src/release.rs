@@ -0,0 +1,210 @@
+use crate::emit::{Directive, Sink};
+use crate::gitversion::{GitVersion, VersionSource};
+use crate::{Error, Result};
+use regex::Regex;
+use std::env;
+
+/// Determines when a [`ReleasePolicy`] is enforced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReleaseCondition {
+    /// The build script's `PROFILE` is the given value. `PROFILE` is `release` for the
+    /// `release` profile and all profiles inheriting from it. The default is `release`.
+    Profile(String),
+    /// The environment variable is set to a non-empty value, e.g. `CI` or `RELEASE`.
+    EnvVar(String),
+    /// Always; useful if the caller decides whether to set the policy.
+    Always,
+}
+
+impl Default for ReleaseCondition {
+    fn default() -> Self {
+        ReleaseCondition::Profile("release".into())
+    }
+}
+
+impl ReleaseCondition {
+    fn applies(&self) -> bool {
+        match self {
+            ReleaseCondition::Profile(profile) => {
+                matches!(env::var("PROFILE"), Ok(p) if p == *profile)
+            }
+            ReleaseCondition::EnvVar(name) => matches!(env::var_os(name), Some(v) if !v.is_empty()),
+            ReleaseCondition::Always => true,
+        }
+    }
+}
+
+/// Requirements the calculated version has to meet in release builds, e.g. to keep
+/// binaries from being built from dirty trees or feature branches.
+///
+/// Versions of packaged crates, read from `.cargo_vcs_info.json`, are not checked, so
+/// the policy does not affect release builds of dependents.
+///
+/// ```
+/// use dotnet_gitversion_build::{ReleaseCondition, ReleasePolicy};
+///
+/// let policy = ReleasePolicy::new()
+///     .deny_uncommitted_changes()
+///     .deny_pre_release()
+///     .require_tag()
+///     .allow_branch("^(main|release/.+)$")
+///     .condition(ReleaseCondition::EnvVar("CI".into()));
+/// # assert!(policy.validate().is_ok());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReleasePolicy {
+    condition: ReleaseCondition,
+    deny_uncommitted_changes: bool,
+    deny_pre_release: bool,
+    require_tag: bool,
+    branches: Vec<String>,
+}
+
+impl ReleasePolicy {
+    /// Creates a policy without requirements, enforced in the `release` profile.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets when the policy is enforced. Defaults to the `release` profile.
+    pub fn condition(mut self, condition: ReleaseCondition) -> Self {
+        self.condition = condition;
+        self
+    }
+
+    /// Requires `UncommittedChanges` to be `0`.
+    pub fn deny_uncommitted_changes(mut self) -> Self {
+        self.deny_uncommitted_changes = true;
+        self
+    }
+
+    /// Requires an empty `PreReleaseTag`.
+    pub fn deny_pre_release(mut self) -> Self {
+        self.deny_pre_release = true;
+        self
+    }
+
+    /// Requires `HEAD` to be on a version tag, i.e. `CommitsSinceVersionSource` to be `0`.
+    pub fn require_tag(mut self) -> Self {
+        self.require_tag = true;
+        self
+    }
+
+    /// Allows releases from branches whose `BranchName` matches `regex`. Without any
+    /// allowed branches, all branches are allowed. Anchor the regex to match whole names.
+    pub fn allow_branch<S: Into<String>>(mut self, regex: S) -> Self {
+        self.branches.push(regex.into());
+        self
+    }
+
+    /// Fails with [`Error::InvalidBranchRegex`] if an allowed branch is not a valid regex.
+    pub fn validate(&self) -> Result<()> {
+        self.branch_regexes().map(|_| ())
+    }
+
+    fn branch_regexes(&self) -> Result<Vec<Regex>> {
+        self.branches
+            .iter()
+            .map(|pattern| {
+                Regex::new(pattern).map_err(|source| Error::InvalidBranchRegex {
+                    regex: pattern.clone(),
+                    source,
+                })
+            })
+            .collect()
+    }
+
+    /// Reports the variables the [`condition`](Self::condition) depends on.
+    pub(crate) fn emit_rerun_directives(&self, sink: &mut dyn Sink) {
+        if let ReleaseCondition::EnvVar(name) = &self.condition {
+            sink.emit(Directive::RerunIfEnvChanged(name));
+        }
+    }
+
+    /// Fails with [`Error::ReleasePolicyViolated`] listing all unmet requirements,
+    /// if the [`condition`](Self::condition) applies.
+    pub(crate) fn check(&self, gv: &GitVersion) -> Result<()> {
+        if gv.source == VersionSource::VcsInfo || !self.condition.applies() {
+            return Ok(());
+        }
+        self.violations(gv)
+    }
+
+    fn violations(&self, gv: &GitVersion) -> Result<()> {
+        let mut violations = Vec::new();
+        if self.deny_uncommitted_changes && gv.uncommitted_changes > 0 {
+            violations.push(format!("{} uncommitted changes", gv.uncommitted_changes));
+        }
+        if self.deny_pre_release && !gv.pre_release_tag.is_empty() {
+            violations.push(format!("pre-release tag {:?}", gv.pre_release_tag));
+        }
+        if self.require_tag && gv.commits_since_version_source > 0 {
+            violations.push(format!(
+                "{} commits since the version tag",
+                gv.commits_since_version_source
+            ));
+        }
+        let branches = self.branch_regexes()?;
+        if !branches.is_empty() && !branches.iter().any(|r| r.is_match(&gv.branch_name)) {
+            violations.push(format!(
+                "branch {:?} does not match {}",
+                gv.branch_name,
+                self.branches.join(", ")
+            ));
+        }
+        match violations.is_empty() {
+            true => Ok(()),
+            false => Err(Error::ReleasePolicyViolated(violations)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::GITVERSION_6_JSON;
+
+    #[test]
+    pub fn violations_are_collected() -> Result<()> {
+        let mut gv = GitVersion::from_json(GITVERSION_6_JSON)?;
+        gv.uncommitted_changes = 2;
+        gv.pre_release_tag = "alpha.1".into();
+        gv.commits_since_version_source = 3;
+        gv.branch_name = "feature/x".into();
+
+        let policy = ReleasePolicy::new().condition(ReleaseCondition::Always);
+        policy.check(&gv)?;
+
+        let policy = policy
+            .deny_uncommitted_changes()
+            .deny_pre_release()
+            .require_tag()
+            .allow_branch("^main$")
+            .allow_branch("^release/");
+        match policy.check(&gv) {
+            Err(e @ Error::ReleasePolicyViolated(_)) => assert_eq!(
+                e.to_string(),
+                "release policy violated: 2 uncommitted changes; pre-release tag \"alpha.1\"; \
+                 3 commits since the version tag; \
+                 branch \"feature/x\" does not match ^main$, ^release/"
+            ),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        gv.source = VersionSource::VcsInfo;
+        policy.check(&gv)?;
+
+        gv.source = VersionSource::Tool;
+        gv.uncommitted_changes = 0;
+        gv.pre_release_tag.clear();
+        gv.commits_since_version_source = 0;
+        gv.branch_name = "release/1.2".into();
+        policy.check(&gv)?;
+
+        assert!(matches!(
+            ReleasePolicy::new().allow_branch("(").validate(),
+            Err(Error::InvalidBranchRegex { .. })
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+use crate::emit::{Directive, Sink};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The name of the GitVersion configuration file.
+pub(crate) const CONFIG_FILE: &str = "GitVersion.yml";
+
+/// The directories of a discovered Git repository.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Repository {
+    /// The root of the working tree.
+    pub work_dir: PathBuf,
+    /// The per-worktree Git directory holding `HEAD` and `index`.
+    pub git_dir: PathBuf,
+    /// The Git directory shared by all worktrees holding the refs.
+    pub common_dir: PathBuf,
+}
+
+impl Repository {
+    /// Finds the repository containing `start`, following `gitdir:` files
+    /// as used by worktrees and submodules.
+    pub fn discover(start: &Path) -> Option<Self> {
+        for dir in start.ancestors() {
+            let dot_git = dir.join(".git");
+            let git_dir = if dot_git.is_dir() {
+                dot_git
+            } else if dot_git.is_file() {
+                let content = fs::read_to_string(&dot_git).ok()?;
+                let target = content.trim().strip_prefix("gitdir:")?.trim();
+                dir.join(target)
+            } else {
+                continue;
+            };
+
+            let common_dir = match fs::read_to_string(git_dir.join("commondir")) {
+                Ok(common) => git_dir.join(common.trim()),
+                Err(_) => git_dir.clone(),
+            };
+
+            return Some(Self {
+                work_dir: dir.to_path_buf(),
+                git_dir,
+                common_dir,
+            });
+        }
+        None
+    }
+
+    /// The symbolic ref `HEAD` points to, e.g. `refs/heads/main`,
+    /// or `None` if `HEAD` is detached.
+    pub fn head_ref(&self) -> Option<String> {
+        let head = fs::read_to_string(self.git_dir.join("HEAD")).ok()?;
+        head.trim()
+            .strip_prefix("ref:")
+            .map(|r| r.trim().to_owned())
+    }
+
+    /// The commit `HEAD` points to, resolving both loose and packed refs,
+    /// or `None` if the current branch has no commits yet.
+    pub fn head_sha(&self) -> Option<String> {
+        let head = fs::read_to_string(self.git_dir.join("HEAD")).ok()?;
+        let head_ref = match head.trim().strip_prefix("ref:") {
+            Some(head_ref) => head_ref.trim(),
+            None => return Some(head.trim().to_owned()),
+        };
+        if let Ok(sha) = fs::read_to_string(self.common_dir.join(head_ref)) {
+            return Some(sha.trim().to_owned());
+        }
+        let packed = fs::read_to_string(self.common_dir.join("packed-refs")).ok()?;
+        packed.lines().find_map(|line| {
+            let (sha, name) = line.split_once(' ')?;
+            if name == head_ref {
+                Some(sha.to_owned())
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Whether the repository is a shallow clone lacking part of the history.
+    pub fn is_shallow(&self) -> bool {
+        self.common_dir.join("shallow").is_file()
+    }
+
+    /// The files whose changes affect the version calculated for this repository.
+    pub fn watched_paths(&self, config_dirs: &[&Path]) -> Vec<PathBuf> {
+        let mut paths = vec![
+            self.git_dir.join("HEAD"),
+            self.git_dir.join("index"),
+            self.common_dir.join("packed-refs"),
+            self.common_dir.join("refs").join("tags"),
+            self.common_dir.join("shallow"),
+        ];
+
+        // A branch ref only present in `packed-refs` has no file yet;
+        // watch the closest existing directory it will be created in.
+        if let Some(head_ref) = self.head_ref() {
+            let ref_path = self.common_dir.join(head_ref);
+            if let Some(existing) = ref_path
+                .ancestors()
+                .take_while(|p| p.starts_with(&self.common_dir))
+                .find(|p| p.exists())
+            {
+                paths.push(existing.to_path_buf());
+            }
+        }
+
+        for dir in config_dirs
+            .iter()
+            .copied()
+            .chain(Some(self.work_dir.as_path()))
+        {
+            paths.push(dir.join(CONFIG_FILE));
+        }
+
+        // Cargo considers missing files as always changed.
+        let mut existing: Vec<PathBuf> = Vec::new();
+        for path in paths {
+            if path.exists() && !existing.contains(&path) {
+                existing.push(path);
+            }
+        }
+        existing
+    }
+}
+
+/// Prints `cargo:rerun-if-changed` directives for the Git state of the repository
+/// containing `start`.
+pub(crate) fn emit_rerun_directives(start: &Path, sink: &mut dyn Sink) {
+    if let Some(repo) = Repository::discover(start) {
+        for path in repo.watched_paths(&[start]) {
+            sink.emit(Directive::RerunIfChanged(&path));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    pub fn discovers_worktree_via_gitdir_file() -> std::io::Result<()> {
+        let root = TempDir::new()?;
+        let main = root.path().join("main");
+        let worktree_git = main.join(".git").join("worktrees").join("wt");
+        fs::create_dir_all(&worktree_git)?;
+        fs::create_dir_all(main.join(".git").join("refs").join("tags"))?;
+        fs::write(worktree_git.join("commondir"), "../..")?;
+        fs::write(worktree_git.join("HEAD"), "ref: refs/heads/feature/x\n")?;
+
+        let wt = root.path().join("wt");
+        fs::create_dir_all(wt.join("crate"))?;
+        fs::write(
+            wt.join(".git"),
+            format!("gitdir: {}\n", worktree_git.display()),
+        )?;
+
+        let repo = Repository::discover(&wt.join("crate")).expect("repository");
+        assert_eq!(repo.work_dir, wt);
+        assert_eq!(repo.git_dir, worktree_git);
+        assert_eq!(repo.head_ref().as_deref(), Some("refs/heads/feature/x"));
+
+        let paths = repo.watched_paths(&[]);
+        assert!(paths.contains(&worktree_git.join("HEAD")));
+        assert!(paths.contains(&worktree_git.join("../..").join("refs").join("tags")));
+        Ok(())
+    }
+}
@@ -0,0 +1,329 @@
+use crate::builder::DEFAULT_EXECUTABLE;
+use crate::emit::{self, Sink};
+use crate::{process, Error, Result};
+use semver::{Version, VersionReq};
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+/// The local tool manifests `dotnet tool restore` looks for, relative to a directory.
+const TOOL_MANIFESTS: &[&str] = &[".config/dotnet-tools.json", "dotnet-tools.json"];
+
+/// A command line invoking GitVersion, e.g. `dotnet tool run dotnet-gitversion`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tool {
+    program: OsString,
+    args: Vec<OsString>,
+    version: Option<String>,
+}
+
+impl Tool {
+    /// Invokes the given executable directly.
+    pub fn new<S: Into<OsString>>(program: S) -> Self {
+        Self {
+            program: program.into(),
+            args: Vec::new(),
+            version: None,
+        }
+    }
+
+    fn dotnet(args: &[&str]) -> Self {
+        Self {
+            program: "dotnet".into(),
+            args: args.iter().map(OsString::from).collect(),
+            version: None,
+        }
+    }
+
+    /// The invocations considered in order when no executable is configured: the global
+    /// `dotnet-gitversion` shim, `dotnet gitversion` and `dotnet tool run dotnet-gitversion`.
+    pub fn candidates() -> Vec<Tool> {
+        vec![
+            Tool::new(DEFAULT_EXECUTABLE),
+            Tool::dotnet(&["gitversion"]),
+            Tool::dotnet(&["tool", "run", "dotnet-gitversion"]),
+        ]
+    }
+
+    /// A command running the tool, to which the GitVersion arguments are appended.
+    pub(crate) fn command(&self) -> Command {
+        let mut command = Command::new(&self.program);
+        command.args(&self.args);
+        command
+    }
+
+    /// The version pinned in the local tool manifest, if discovered from one.
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    /// Runs `/version` in `dir`, returning `None` if the tool is unavailable.
+    /// Fails with [`Error::Timeout`] if it does not finish within `timeout`.
+    pub(crate) fn query_version(
+        &self,
+        dir: &Path,
+        timeout: Option<Duration>,
+    ) -> Result<Option<String>> {
+        let mut command = self.command();
+        command
+            .arg("/version")
+            .current_dir(dir)
+            .stderr(Stdio::null());
+        let out = match process::output(&mut command, timeout) {
+            Ok(out) => out,
+            Err(e @ Error::Timeout { .. }) => return Err(e),
+            Err(_) => return Ok(None),
+        };
+        if !out.status.success() {
+            return Ok(None);
+        }
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        Ok(stdout
+            .lines()
+            .rev()
+            .map(str::trim)
+            .find(|l| !l.is_empty())
+            .map(str::to_owned))
+    }
+}
+
+impl Display for Tool {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.program.to_string_lossy())?;
+        for arg in &self.args {
+            write!(f, " {}", arg.to_string_lossy())?;
+        }
+        Ok(())
+    }
+}
+
+/// Finds the first of the [`Tool::candidates`] installed for `dir`, without starting .NET:
+/// the `dotnet-gitversion` shim on `PATH`, the shim in the global tools directory
+/// (`~/.dotnet/tools`) or a local tool manifest listing it, for the latter two with
+/// `dotnet` on `PATH`.
+///
+/// With `restore`, `dotnet tool restore` is run before considering the `dotnet` based
+/// candidates if `dir` is covered by a local tool manifest, killed after `timeout`.
+pub(crate) fn discover(
+    dir: &Path,
+    restore: bool,
+    timeout: Option<Duration>,
+    sink: &mut dyn Sink,
+) -> Result<Tool> {
+    let path = env::var_os("PATH").unwrap_or_default();
+    let candidates = Tool::candidates();
+    if find_in_path(DEFAULT_EXECUTABLE, &path).is_some() {
+        return Ok(candidates[0].clone());
+    }
+
+    let manifest = find_tool_manifest(dir);
+    if restore && manifest.is_some() {
+        restore_tools(dir, timeout, sink);
+    }
+    if find_in_path("dotnet", &path).is_some() {
+        if let Some(tools_dir) = global_tools_dir() {
+            if find_in_path(DEFAULT_EXECUTABLE, tools_dir.as_os_str()).is_some() {
+                return Ok(candidates[1].clone());
+            }
+        }
+        let pinned = manifest.and_then(|manifest| pinned_version(&manifest));
+        if let Some(version) = pinned {
+            return Ok(Tool {
+                version: Some(version),
+                ..candidates[2].clone()
+            });
+        }
+    }
+
+    let tried: Vec<String> = candidates.iter().map(ToString::to_string).collect();
+    Err(Error::ToolNotFound(tried.join(", ")))
+}
+
+/// Finds the executable `name` in the directories listed in `path`,
+/// formatted like the `PATH` variable.
+fn find_in_path(name: &str, path: &OsStr) -> Option<PathBuf> {
+    let file_name = format!("{}{}", name, env::consts::EXE_SUFFIX);
+    env::split_paths(path)
+        .map(|dir| dir.join(&file_name))
+        .find(|candidate| candidate.is_file())
+}
+
+/// The directory `dotnet tool install --global` puts the tool shims in.
+fn global_tools_dir() -> Option<PathBuf> {
+    let home = env::var_os("DOTNET_CLI_HOME")
+        .or_else(|| env::var_os("HOME"))
+        .or_else(|| env::var_os("USERPROFILE"))?;
+    Some(PathBuf::from(home).join(".dotnet").join("tools"))
+}
+
+/// The version of the tool providing the `dotnet-gitversion` command in the local tool
+/// manifest at `path`, or `None` if it is not listed.
+fn pinned_version(path: &Path) -> Option<String> {
+    let manifest: serde_json::Value = serde_json::from_str(&fs::read_to_string(path).ok()?).ok()?;
+    manifest
+        .get("tools")?
+        .as_object()?
+        .values()
+        .find(|tool| {
+            let commands = tool.get("commands").and_then(serde_json::Value::as_array);
+            matches!(commands, Some(commands) if commands
+                .iter()
+                .any(|command| command.as_str() == Some(DEFAULT_EXECUTABLE)))
+        })
+        .and_then(|tool| tool.get("version")?.as_str().map(str::to_owned))
+}
+
+/// The closest local tool manifest in `dir` or its ancestors.
+fn find_tool_manifest(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .flat_map(|dir| {
+            TOOL_MANIFESTS
+                .iter()
+                .map(move |manifest| dir.join(manifest))
+        })
+        .find(|path| path.is_file())
+}
+
+/// Checks `version`, as reported by `/version`, against `requirement`.
+pub(crate) fn check_version(version: Option<&str>, requirement: &VersionReq) -> Result<()> {
+    let version = version.unwrap_or_default();
+    // GitVersion reports its informational version, e.g. `5.12.0+Branch.support-5.x.Sha.3f75764`.
+    let core = version.split('+').next().unwrap_or_default();
+    match Version::parse(core) {
+        Ok(parsed) if requirement.matches(&parsed) => Ok(()),
+        _ => Err(Error::ToolVersionMismatch {
+            version: version.to_owned(),
+            requirement: requirement.to_string(),
+        }),
+    }
+}
+
+/// Runs `dotnet tool restore`, reporting failures and timeouts as warnings only;
+/// the tool may still be available otherwise.
+fn restore_tools(dir: &Path, timeout: Option<Duration>, sink: &mut dyn Sink) {
+    let mut command = Command::new("dotnet");
+    command.args(["tool", "restore"]).current_dir(dir);
+    let out = match process::output(&mut command, timeout) {
+        Ok(out) => out,
+        Err(e @ Error::Timeout { .. }) => {
+            emit::warning(sink, &format_args!("dotnet tool restore failed: {}", e));
+            return;
+        }
+        Err(_) => return,
+    };
+    if !out.status.success() {
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        emit::warning(
+            sink,
+            &format_args!(
+                "dotnet tool restore failed with {}: {}",
+                out.status,
+                stderr.trim()
+            ),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    pub fn candidates_are_displayed_as_command_lines() {
+        let candidates: Vec<String> = Tool::candidates().iter().map(ToString::to_string).collect();
+        assert_eq!(
+            candidates,
+            vec![
+                "dotnet-gitversion",
+                "dotnet gitversion",
+                "dotnet tool run dotnet-gitversion"
+            ]
+        );
+    }
+
+    #[test]
+    pub fn version_query_is_killed_on_timeout() -> Result<()> {
+        let tool = Tool {
+            program: "sh".into(),
+            args: vec!["-c".into(), "exec sleep 10".into()],
+            version: None,
+        };
+        let dir = TempDir::new()?;
+        let result = tool.query_version(dir.path(), Some(Duration::from_millis(500)));
+        assert!(matches!(result, Err(Error::Timeout { .. })));
+
+        let missing = Tool::new("does-not-exist").query_version(dir.path(), None)?;
+        assert_eq!(missing, None);
+        Ok(())
+    }
+
+    #[test]
+    pub fn executables_are_found_without_running_them() -> Result<()> {
+        let bin = TempDir::new()?;
+        let path = env::join_paths(vec![PathBuf::from("/does-not-exist"), bin.path().into()])
+            .expect("path");
+        assert_eq!(find_in_path("dotnet-gitversion", &path), None);
+
+        let shim = bin
+            .path()
+            .join(format!("dotnet-gitversion{}", env::consts::EXE_SUFFIX));
+        fs::write(&shim, "")?;
+        assert_eq!(find_in_path("dotnet-gitversion", &path), Some(shim));
+
+        let manifest = bin.path().join("dotnet-tools.json");
+        fs::write(
+            &manifest,
+            r#"{
+                "version": 1,
+                "isRoot": true,
+                "tools": {
+                    "gitversion.tool": {
+                        "version": "5.12.0",
+                        "commands": ["dotnet-gitversion"]
+                    }
+                }
+            }"#,
+        )?;
+        assert_eq!(pinned_version(&manifest).as_deref(), Some("5.12.0"));
+        fs::write(&manifest, r#"{"version": 1, "tools": {}}"#)?;
+        assert_eq!(pinned_version(&manifest), None);
+        Ok(())
+    }
+
+    #[test]
+    pub fn tool_manifest_is_found_in_ancestors() -> Result<()> {
+        let root = TempDir::new()?;
+        let crate_dir = root.path().join("crates").join("a");
+        fs::create_dir_all(&crate_dir)?;
+        assert_eq!(find_tool_manifest(&crate_dir), None);
+
+        fs::create_dir_all(root.path().join(".config"))?;
+        fs::write(root.path().join(".config/dotnet-tools.json"), "{}")?;
+        assert_eq!(
+            find_tool_manifest(&crate_dir),
+            Some(root.path().join(".config/dotnet-tools.json"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    pub fn version_requirement_is_checked() {
+        let requirement = VersionReq::parse(">=5.12, <6").expect("requirement");
+        assert!(check_version(Some("5.12.0+Branch.support-5.x.Sha.3f75764"), &requirement).is_ok());
+
+        match check_version(Some("5.6.10"), &requirement) {
+            Err(e @ Error::ToolVersionMismatch { .. }) => assert_eq!(
+                e.to_string(),
+                "GitVersion 5.6.10 does not satisfy the required version >=5.12, <6"
+            ),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(check_version(None, &requirement).is_err());
+    }
+}
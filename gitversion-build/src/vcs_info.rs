@@ -0,0 +1,200 @@
+use crate::fallback::cargo_pkg_version_parts;
+use crate::gitversion::{GitVersion, VersionParts, VersionSource};
+use crate::repository::Repository;
+use crate::{Error, Result};
+use serde::Deserialize;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+/// The file `cargo package` adds to the root of packaged crates.
+pub(crate) const VCS_INFO_FILE: &str = ".cargo_vcs_info.json";
+
+/// The contents of `.cargo_vcs_info.json`.
+#[derive(Deserialize)]
+struct VcsInfo {
+    git: GitInfo,
+    /// The directory of the crate within the repository, empty for the root.
+    /// Missing in files written before Cargo 1.58.
+    #[serde(default)]
+    path_in_vcs: String,
+}
+
+#[derive(Deserialize)]
+struct GitInfo {
+    sha1: String,
+    #[serde(default)]
+    dirty: bool,
+}
+
+/// Builds the version of a packaged crate, e.g. from a registry or a vendored copy,
+/// from `CARGO_PKG_VERSION` and the commit recorded by `cargo package`. Outside of
+/// Cargo, e.g. in `cargo gitversion`, the version is read from `Cargo.toml` instead.
+///
+/// Returns `None` for crates not built from a package, including a checkout of the
+/// packaged repository the file was copied into: there, the crate lives at `path_in_vcs`
+/// of its repository and the version is calculated from the history as usual.
+pub(crate) fn from_vcs_info(manifest_dir: &Path) -> Result<Option<GitVersion>> {
+    let json = match fs::read_to_string(manifest_dir.join(VCS_INFO_FILE)) {
+        Ok(json) => json,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let info = parse(&json)?;
+    if is_checkout(manifest_dir, &info.path_in_vcs) {
+        return Ok(None);
+    }
+    let parts = match cargo_pkg_version_parts() {
+        Err(Error::MissingEnvVar(_)) => manifest_version_parts(manifest_dir)?,
+        parts => parts?,
+    };
+    Ok(Some(from_info(info, parts)))
+}
+
+/// Reads `[package] version` from the `Cargo.toml` in `manifest_dir`. The manifests
+/// `cargo package` writes are normalized, so the version is a plain string there.
+fn manifest_version_parts(manifest_dir: &Path) -> Result<VersionParts> {
+    let path = manifest_dir.join("Cargo.toml");
+    let content = fs::read_to_string(&path)?;
+    let invalid = |message: String| Error::InvalidManifest {
+        path: path.clone(),
+        message,
+    };
+    let version =
+        package_version(&content).ok_or_else(|| invalid("no [package] version".into()))?;
+    let version =
+        semver::Version::parse(&version).map_err(|e| invalid(format!("{}: {}", version, e)))?;
+
+    let mut parts = VersionParts {
+        major: version.major as u32,
+        minor: version.minor as u32,
+        patch: version.patch as u32,
+        ..VersionParts::default()
+    };
+    parts.set_pre_release(version.pre.as_str());
+    Ok(parts)
+}
+
+/// The `version = "..."` line of the `[package]` table.
+fn package_version(manifest: &str) -> Option<String> {
+    let mut in_package = false;
+    for line in manifest.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_package = line == "[package]";
+            continue;
+        }
+        if !in_package {
+            continue;
+        }
+        let value = match line.strip_prefix("version") {
+            Some(rest) => rest.trim_start().strip_prefix('=')?.trim(),
+            None => continue,
+        };
+        return value
+            .strip_prefix('"')
+            .and_then(|value| value.split('"').next())
+            .map(str::to_owned);
+    }
+    None
+}
+
+fn parse(json: &str) -> Result<VcsInfo> {
+    serde_json::from_str(json).map_err(|source| Error::InvalidJson {
+        field: None,
+        source,
+    })
+}
+
+/// Whether `manifest_dir` is `path_in_vcs` of the Git repository containing it.
+fn is_checkout(manifest_dir: &Path, path_in_vcs: &str) -> bool {
+    let repo = match Repository::discover(manifest_dir) {
+        Some(repo) => repo,
+        None => return false,
+    };
+    let expected = repo.work_dir.join(path_in_vcs);
+    match (expected.canonicalize(), manifest_dir.canonicalize()) {
+        (Ok(expected), Ok(actual)) => expected == actual,
+        _ => false,
+    }
+}
+
+fn from_info(info: VcsInfo, mut parts: VersionParts) -> GitVersion {
+    parts.sha = info.git.sha1;
+    // `cargo package --allow-dirty` only records that there were changes, not how many.
+    parts.uncommitted_changes = if info.git.dirty { 1 } else { 0 };
+
+    let mut gv = GitVersion::from(parts);
+    gv.is_fallback = true;
+    gv.source = VersionSource::VcsInfo;
+    gv
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn version_uses_packaged_commit() -> Result<()> {
+        let json = r#"{
+            "git": {
+                "sha1": "645a21e7b6358e9b72978a1b46cbd6c55a85a9af",
+                "dirty": true
+            },
+            "path_in_vcs": "gitversion-build"
+        }"#;
+        let mut parts = VersionParts {
+            major: 0,
+            minor: 3,
+            patch: 0,
+            ..VersionParts::default()
+        };
+        parts.set_pre_release("rc.1");
+
+        let gv = from_info(parse(json)?, parts);
+        assert_eq!(gv.full_semver, "0.3.0-rc.1");
+        assert_eq!(gv.short_sha, "645a21e");
+        assert_eq!(
+            gv.informational_version,
+            "0.3.0-rc.1+Sha.645a21e7b6358e9b72978a1b46cbd6c55a85a9af"
+        );
+        assert_eq!(gv.uncommitted_changes, 1);
+        assert!(gv.is_fallback);
+        assert_eq!(gv.source, VersionSource::VcsInfo);
+        Ok(())
+    }
+
+    #[test]
+    pub fn unpackaged_crate_is_ignored() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        assert!(from_vcs_info(dir.path())?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    pub fn version_is_read_from_normalized_manifest() {
+        let manifest = "[package]\nedition = \"2018\"\nname = \"a\"\nversion = \"0.3.0-rc.1\"\n\n\
+                        [dependencies.serde]\nversion = \"1.0\"\n";
+        assert_eq!(package_version(manifest).as_deref(), Some("0.3.0-rc.1"));
+        assert_eq!(package_version("[dependencies]\nversion = \"1.0\"\n"), None);
+    }
+
+    #[test]
+    pub fn checkout_at_path_in_vcs_is_detected() -> Result<()> {
+        let repo = tempfile::TempDir::new()?;
+        fs::create_dir_all(repo.path().join(".git"))?;
+        let crate_dir = repo.path().join("gitversion-build");
+        let package_dir = repo.path().join("target/package/gitversion-build-0.3.0");
+        fs::create_dir_all(&crate_dir)?;
+        fs::create_dir_all(&package_dir)?;
+
+        assert!(is_checkout(&crate_dir, "gitversion-build"));
+        assert!(is_checkout(repo.path(), ""));
+        // `cargo package` verifies the package below `target/`.
+        assert!(!is_checkout(&package_dir, "gitversion-build"));
+        assert!(!is_checkout(&crate_dir, "other"));
+
+        let outside = tempfile::TempDir::new()?;
+        assert!(!is_checkout(outside.path(), ""));
+        Ok(())
+    }
+}